@@ -0,0 +1,81 @@
+pub mod cargo;
+pub mod phase;
+pub mod trace;
+
+use crate::error::MessageError;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A complete line printed from a stream. Lines unfolded from a single line are separate lines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line<'a> {
+    /// Time since start, as printed in the timestamp
+    pub time: Duration,
+    /// Name of the stream, empty when reading stdin
+    pub stream: &'a str,
+    /// Printable text of the line, without control characters and escape sequences
+    pub text: &'a str,
+}
+
+/// Analyzes lines as they are printed, e.g., to export timing or summarize at the end
+pub trait Observer: Send {
+    /// Observes a line, returns a banner to print after the line if it's notable, e.g., starts
+    /// a phase
+    fn line(&mut self, line: &Line) -> Option<String>;
+
+    /// Called when all streams have ended, with the time of the end line. A summary can be
+    /// written to the output, after the end line.
+    fn end(&mut self, time: Duration, output: &mut dyn Write) -> std::io::Result<()>;
+}
+
+/// Profiles analyzing the output of a specific tool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Profile {
+    /// Time spent building each crate
+    Cargo,
+}
+
+impl FromStr for Profile {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cargo" => Ok(Self::Cargo),
+            _ => Err(MessageError(format!(
+                "Unknown profile '{s}', expected 'cargo'"
+            ))),
+        }
+    }
+}
+
+/// Observers shared by the printers of all streams
+#[derive(Clone, Default)]
+pub struct Observers(Arc<Mutex<Vec<Box<dyn Observer>>>>);
+
+impl Observers {
+    pub fn add(&self, observer: impl Observer + 'static) {
+        self.lock().push(Box::new(observer));
+    }
+
+    /// Lets all observers observe a line, returns their banners
+    pub fn line(&self, line: &Line) -> Vec<String> {
+        self.lock()
+            .iter_mut()
+            .filter_map(|observer| observer.line(line))
+            .collect()
+    }
+
+    pub fn end(&self, time: Duration, output: &mut dyn Write) -> std::io::Result<()> {
+        for observer in self.lock().iter_mut() {
+            observer.end(time, output)?;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Box<dyn Observer>>> {
+        // If another thread panicked while observing, we proceed anyway
+        self.0.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
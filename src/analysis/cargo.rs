@@ -0,0 +1,213 @@
+use super::{Line, Observer};
+use crate::output::timestamp;
+use regex::Regex;
+use std::io::Write;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Regex to catch the start of building a crate, e.g., `Compiling regex v1.12.3`
+static START_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(?:Compiling|Checking) ([^ ]+ v[^ ]+)").expect("Start regex should be valid")
+});
+
+/// Regex to catch lines ending the build of the previous crate
+static STOP_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(?:Running|Finished) ").expect("Stop regex should be valid"));
+
+/// Regex to catch the number of warnings cargo summarizes for a crate, e.g.,
+/// ``warning: `linetime` (bin "linetime") generated 2 warnings``
+static WARNINGS_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*warning: `([^`]+)` .*generated ([0-9]+) warnings?")
+        .expect("Warnings regex should be valid")
+});
+
+#[derive(Debug, Default, PartialEq)]
+struct CrateTime {
+    /// Name and version, e.g., `regex v1.12.3`
+    name: String,
+    time: Duration,
+    warnings: u64,
+}
+
+/// Attributes elapsed time to the crates cargo builds and prints a ranking at the end. Cargo
+/// builds crates in parallel but only reports when a crate starts, so the time from a crate
+/// starting until the next crate starts, or the build finishes, is attributed to the crate.
+/// Lines with cargo's progress bar, unfolded to separate lines, are ignored.
+pub struct CargoProfile {
+    crates: Vec<CrateTime>,
+    // Index of the crate currently attributed time, and since when
+    current: Option<(usize, Duration)>,
+    microseconds: bool,
+}
+
+impl CargoProfile {
+    pub fn new(microseconds: bool) -> Self {
+        Self {
+            crates: vec![],
+            current: None,
+            microseconds,
+        }
+    }
+
+    /// Gets the crate with a name, adding it if not seen before. Crates in a warning summary
+    /// are given by name only.
+    fn crate_index(&mut self, name: &str, exact: bool) -> usize {
+        let matches = |crate_time: &CrateTime| {
+            crate_time.name == name || (!exact && crate_time.name.split(' ').next() == Some(name))
+        };
+        match self.crates.iter().position(matches) {
+            Some(index) => index,
+            None => {
+                self.crates.push(CrateTime {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+                self.crates.len() - 1
+            }
+        }
+    }
+
+    fn stop(&mut self, time: Duration) {
+        if let Some((index, start)) = self.current.take() {
+            self.crates[index].time += time.saturating_sub(start);
+        }
+    }
+}
+
+impl Observer for CargoProfile {
+    fn line(&mut self, line: &Line) -> Option<String> {
+        if let Some(captures) = START_REGEX.captures(line.text) {
+            self.stop(line.time);
+            let index = self.crate_index(&captures[1], true);
+            self.current = Some((index, line.time));
+        } else if STOP_REGEX.is_match(line.text) {
+            self.stop(line.time);
+        } else if let Some(captures) = WARNINGS_REGEX.captures(line.text) {
+            let index = self.crate_index(&captures[1], false);
+            self.crates[index].warnings += captures[2].parse::<u64>().unwrap_or_default();
+        }
+        None
+    }
+
+    fn end(&mut self, time: Duration, output: &mut dyn Write) -> std::io::Result<()> {
+        self.stop(time);
+        if self.crates.is_empty() {
+            return Ok(());
+        }
+        self.crates
+            .sort_by_key(|crate_time| std::cmp::Reverse(crate_time.time));
+
+        let name_width = self
+            .crates
+            .iter()
+            .map(|crate_time| crate_time.name.chars().count())
+            .chain(["Crate".len()])
+            .max()
+            .unwrap_or_default();
+        let time_width = timestamp::format(time, self.microseconds).len();
+        writeln!(
+            output,
+            "{:<name_width$}  {:>time_width$}  {:>6}  Warnings",
+            "Crate", "Time", "Share"
+        )?;
+        for crate_time in &self.crates {
+            let share = if time.is_zero() {
+                0.0
+            } else {
+                100.0 * crate_time.time.as_secs_f64() / time.as_secs_f64()
+            };
+            writeln!(
+                output,
+                "{:<name_width$}  {:>time_width$}  {:>6}  {:>8}",
+                crate_time.name,
+                timestamp::format(crate_time.time, self.microseconds),
+                format!("{share:.1}%"),
+                crate_time.warnings
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe(profile: &mut CargoProfile, lines: &[(u64, &str)]) {
+        for (millis, text) in lines {
+            profile.line(&Line {
+                time: Duration::from_millis(*millis),
+                stream: "stderr",
+                text,
+            });
+        }
+    }
+
+    fn ranking(profile: &mut CargoProfile, end: u64) -> String {
+        let mut output = vec![];
+        profile
+            .end(Duration::from_millis(end), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn time_is_attributed_to_crates_in_plain_mode() {
+        let mut profile = CargoProfile::new(false);
+        observe(
+            &mut profile,
+            &[
+                (0, "   Compiling regex v1.12.3"),
+                (1000, "   Compiling linetime v1.0.2 (/src/linetime)"),
+                (1500, "warning: unused variable: `x`"),
+                (
+                    1600,
+                    "warning: `linetime` (bin \"linetime\") generated 1 warning",
+                ),
+                (
+                    4000,
+                    "    Finished `dev` profile [unoptimized + debuginfo] target(s)",
+                ),
+                (4500, "     Running `target/debug/linetime`"),
+            ],
+        );
+        assert_eq!(
+            concat!(
+                "Crate                 Time   Share  Warnings\n",
+                "linetime v1.0.2  00:03.000   60.0%         1\n",
+                "regex v1.12.3    00:01.000   20.0%         0\n",
+            ),
+            ranking(&mut profile, 5000)
+        );
+    }
+
+    #[test]
+    fn progress_bar_lines_are_ignored() {
+        let mut profile = CargoProfile::new(false);
+        observe(
+            &mut profile,
+            &[
+                (0, "    Checking memchr v2.7.4"),
+                (100, "    Building [>         ] 0/2: memchr"),
+                (500, "    Building [=====>    ] 1/2: memchr"),
+                (1000, "    Checking regex v1.12.3"),
+                (1100, "    Building [=====>    ] 1/2: regex"),
+            ],
+        );
+        assert_eq!(
+            concat!(
+                "Crate               Time   Share  Warnings\n",
+                "memchr v2.7.4  00:01.000   50.0%         0\n",
+                "regex v1.12.3  00:01.000   50.0%         0\n",
+            ),
+            ranking(&mut profile, 2000)
+        );
+    }
+
+    #[test]
+    fn nothing_is_printed_without_crates() {
+        let mut profile = CargoProfile::new(false);
+        observe(&mut profile, &[(0, "hello")]);
+        assert_eq!("", ranking(&mut profile, 1000));
+    }
+}
@@ -0,0 +1,316 @@
+use super::{Line, Observer};
+use crate::error::MessageError;
+use crate::output::timestamp;
+use regex::Regex;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A pattern starting a phase when a line matches. Given as `NAME=REGEX`, or only `REGEX` to
+/// name the phase after the matching line.
+#[derive(Clone, Debug)]
+pub struct PhaseRule {
+    name: Option<String>,
+    regex: Regex,
+}
+
+impl FromStr for PhaseRule {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A name can't contain characters used in regexes, to allow '=' in regexes
+        let (name, regex) = match s.split_once('=') {
+            Some((name, regex))
+                if !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || " _-".contains(c)) =>
+            {
+                (Some(name.to_string()), regex)
+            }
+            _ => (None, s),
+        };
+        let regex = Regex::new(regex)
+            .map_err(|error| MessageError(format!("Invalid phase regex '{regex}': {error}")))?;
+        Ok(Self { name, regex })
+    }
+}
+
+/// A phase that has ended
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub name: String,
+    /// Nesting level, 0 for the outermost phases
+    pub level: usize,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+/// Keeps track of the current phases. Phases nest in the order the rules are given, i.e., a
+/// phase started by an earlier rule is an outer phase to phases started by later rules. A
+/// started phase ends phases at the same or inner levels.
+#[derive(Clone, Debug, Default)]
+pub struct PhaseTracker {
+    rules: Vec<PhaseRule>,
+    // Started phases, from outermost to innermost
+    open: Vec<(usize, String, Duration)>,
+}
+
+impl PhaseTracker {
+    pub fn new(rules: Vec<PhaseRule>) -> Self {
+        Self {
+            rules,
+            open: vec![],
+        }
+    }
+
+    /// Checks a line for a new phase. Returns the name of the started phase, if any, and the
+    /// phases it ended.
+    pub fn line(&mut self, time: Duration, text: &str) -> (Option<String>, Vec<Span>) {
+        let Some((level, rule)) = self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.regex.is_match(text))
+        else {
+            return (None, vec![]);
+        };
+        let name = rule.name.clone().unwrap_or_else(|| text.trim().to_string());
+        let ended = self.close(level, time);
+        self.open.push((level, name.clone(), time));
+        (Some(name), ended)
+    }
+
+    /// Gets the name of the innermost started phase
+    pub fn current(&self) -> Option<&str> {
+        self.open.last().map(|(_, name, _)| name.as_str())
+    }
+
+    /// Ends all phases
+    pub fn end(&mut self, time: Duration) -> Vec<Span> {
+        self.close(0, time)
+    }
+
+    /// Ends phases at a level and inner levels, innermost first
+    fn close(&mut self, level: usize, time: Duration) -> Vec<Span> {
+        let mut ended = vec![];
+        while let Some((open_level, name, start)) = self.open.pop() {
+            if open_level < level {
+                self.open.push((open_level, name, start));
+                break;
+            }
+            // Lines of different streams can end out of order, so a phase can end at a time
+            // before it started
+            ended.push(Span {
+                name,
+                level: open_level,
+                start,
+                end: time.max(start),
+            });
+        }
+        ended
+    }
+}
+
+/// Prints a banner when a phase starts and a table of phase durations at the end
+pub struct PhaseSummary {
+    phases: PhaseTracker,
+    ended: Vec<Span>,
+    microseconds: bool,
+}
+
+impl PhaseSummary {
+    pub fn new(rules: Vec<PhaseRule>, microseconds: bool) -> Self {
+        Self {
+            phases: PhaseTracker::new(rules),
+            ended: vec![],
+            microseconds,
+        }
+    }
+}
+
+impl Observer for PhaseSummary {
+    fn line(&mut self, line: &Line) -> Option<String> {
+        let (started, ended) = self.phases.line(line.time, line.text);
+        self.ended.extend(ended);
+        started.map(|name| format!("\u{25b6} {name}"))
+    }
+
+    fn end(&mut self, time: Duration, output: &mut dyn Write) -> std::io::Result<()> {
+        self.ended.extend(self.phases.end(time));
+        if self.ended.is_empty() {
+            return Ok(());
+        }
+        // Outer phases before inner, which are indented
+        self.ended.sort_by_key(|span| (span.start, span.level));
+        let rows = self
+            .ended
+            .iter()
+            .map(|span| {
+                let duration = span.end - span.start;
+                let share = if time.is_zero() {
+                    0.0
+                } else {
+                    100.0 * duration.as_secs_f64() / time.as_secs_f64()
+                };
+                (
+                    format!("{}{}", "  ".repeat(span.level), span.name),
+                    timestamp::format(duration, self.microseconds),
+                    format!("{share:.1}%"),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let name_width = rows
+            .iter()
+            .map(|(name, _, _)| name.chars().count())
+            .chain(["Phase".len()])
+            .max()
+            .unwrap_or_default();
+        let duration_width = timestamp::format(time, self.microseconds).len();
+        writeln!(
+            output,
+            "{:<name_width$}  {:>duration_width$}  {:>6}",
+            "Phase", "Duration", "Share"
+        )?;
+        for (name, duration, share) in rows {
+            writeln!(
+                output,
+                "{name:<name_width$}  {duration:>duration_width$}  {share:>6}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn tracker(rules: &[&str]) -> PhaseTracker {
+        PhaseTracker::new(rules.iter().map(|rule| rule.parse().unwrap()).collect())
+    }
+
+    fn span(name: &str, level: usize, start: u64, end: u64) -> Span {
+        Span {
+            name: name.to_string(),
+            level,
+            start: secs(start),
+            end: secs(end),
+        }
+    }
+
+    #[test]
+    fn rules_are_parsed_with_and_without_name() {
+        let rule = "Build step=^Step [0-9]+".parse::<PhaseRule>().unwrap();
+        assert_eq!(Some("Build step".to_string()), rule.name);
+        assert!(rule.regex.is_match("Step 3"));
+
+        let rule = "^a=b".parse::<PhaseRule>().unwrap();
+        assert_eq!(None, rule.name);
+        assert!(rule.regex.is_match("a=b"));
+
+        assert!("x=(".parse::<PhaseRule>().is_err());
+    }
+
+    #[test]
+    fn new_phase_ends_previous_phase() {
+        let mut tracker = tracker(&["Compiling"]);
+        assert_eq!((None, vec![]), tracker.line(secs(0), "noise"));
+        assert_eq!(
+            (Some("Compiling foo".to_string()), vec![]),
+            tracker.line(secs(1), "  Compiling foo")
+        );
+        assert_eq!(
+            (
+                Some("Compiling bar".to_string()),
+                vec![span("Compiling foo", 0, 1, 3)]
+            ),
+            tracker.line(secs(3), "Compiling bar")
+        );
+        assert_eq!(vec![span("Compiling bar", 0, 3, 5)], tracker.end(secs(5)));
+    }
+
+    #[test]
+    fn phases_of_earlier_rules_are_outer_phases() {
+        let mut tracker = tracker(&["build=^Building", "test=^Testing", "^Step"]);
+        tracker.line(secs(0), "Building");
+        tracker.line(secs(1), "Step 1");
+        assert_eq!(Some("Step 1"), tracker.current());
+        assert_eq!(
+            vec![span("Step 1", 2, 1, 2)],
+            tracker.line(secs(2), "Step 2").1
+        );
+        assert_eq!(
+            vec![span("Step 2", 2, 2, 3)],
+            tracker.line(secs(3), "Testing").1
+        );
+        tracker.line(secs(4), "Step 1");
+        assert_eq!(
+            vec![
+                span("Step 1", 2, 4, 6),
+                span("test", 1, 3, 6),
+                span("build", 0, 0, 6)
+            ],
+            tracker.line(secs(6), "Building again").1
+        );
+        assert_eq!(vec![span("build", 0, 6, 7)], tracker.end(secs(7)));
+        assert_eq!(None, tracker.current());
+    }
+
+    #[test]
+    fn phases_never_end_before_they_start() {
+        let mut tracker = tracker(&["A=^Build", "B=^Step"]);
+        tracker.line(secs(3), "Build");
+        tracker.line(secs(2), "Step 1");
+        assert_eq!(
+            vec![span("B", 1, 2, 2), span("A", 0, 3, 3)],
+            tracker.line(secs(1), "Build").1
+        );
+        assert_eq!(vec![span("A", 0, 1, 1)], tracker.end(secs(0)));
+
+        let rules = vec!["A=^Build".parse().unwrap()];
+        let mut summary = PhaseSummary::new(rules, false);
+        summary.line(&Line {
+            time: secs(2),
+            stream: "",
+            text: "Build",
+        });
+        let mut output = vec![];
+        summary.end(secs(1), &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("00:00.000"));
+    }
+
+    #[test]
+    fn summary_prints_banners_and_table_of_durations() {
+        let rules = vec!["build=^Building".parse().unwrap(), "^Step".parse().unwrap()];
+        let mut summary = PhaseSummary::new(rules, false);
+        let mut line = |seconds, text| {
+            summary.line(&Line {
+                time: secs(seconds),
+                stream: "",
+                text,
+            })
+        };
+        assert_eq!(None, line(0, "noise"));
+        assert_eq!(Some("\u{25b6} build".to_string()), line(2, "Building"));
+        assert_eq!(Some("\u{25b6} Step one".to_string()), line(3, "Step one"));
+        assert_eq!(Some("\u{25b6} Step two".to_string()), line(4, "Step two"));
+
+        let mut output = vec![];
+        summary.end(secs(10), &mut output).unwrap();
+        assert_eq!(
+            concat!(
+                "Phase        Duration   Share\n",
+                "build       00:08.000   80.0%\n",
+                "  Step one  00:01.000   10.0%\n",
+                "  Step two  00:06.000   60.0%\n",
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use super::phase::{PhaseRule, PhaseTracker, Span};
+use super::{Line, Observer};
+use crate::error::{Result, ResultExt};
+use std::io::Write;
+use std::time::Duration;
+
+/// Thread id of the track with phases, streams get the following ids
+const PHASE_TRACK: usize = 0;
+
+/// Writes lines, and phases, as complete events in Trace Event Format JSON, to view as a timeline
+/// in `chrome://tracing` or Perfetto UI. See
+/// <https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU> for
+/// reference. Each stream is a separate thread track and a line lasts until the next line of any
+/// stream, or the end.
+pub struct TraceWriter {
+    output: Box<dyn Write + Send>,
+    phases: PhaseTracker,
+    // Streams in the order first seen
+    tracks: Vec<String>,
+    // Track, start and text of the last line, written when the next line arrives
+    pending: Option<(usize, Duration, String)>,
+    events_written: usize,
+    // First error when writing, reported at the end
+    error: Option<std::io::Error>,
+}
+
+impl TraceWriter {
+    pub fn create(path: &str, phase_rules: Vec<PhaseRule>) -> Result<Self> {
+        let file =
+            std::fs::File::create(path).error_context(format!("Failed to create file '{path}'"))?;
+        Self::new(Box::new(std::io::BufWriter::new(file)), phase_rules)
+            .error_context("Failed to write trace file")
+    }
+
+    pub fn new(
+        mut output: Box<dyn Write + Send>,
+        phase_rules: Vec<PhaseRule>,
+    ) -> std::io::Result<Self> {
+        write!(output, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+        let mut writer = Self {
+            output,
+            phases: PhaseTracker::new(phase_rules.clone()),
+            tracks: vec![],
+            pending: None,
+            events_written: 0,
+            error: None,
+        };
+        if !phase_rules.is_empty() {
+            writer.track_name(PHASE_TRACK, "phases");
+        }
+        Ok(writer)
+    }
+
+    fn track_name(&mut self, track: usize, name: &str) {
+        self.write_event(serde_json::json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 1,
+            "tid": track,
+            "args": { "name": name },
+        }));
+    }
+
+    fn complete_event(
+        &mut self,
+        track: usize,
+        category: &str,
+        name: &str,
+        start: Duration,
+        end: Duration,
+    ) {
+        self.write_event(serde_json::json!({
+            "name": name,
+            "cat": category,
+            "ph": "X",
+            "ts": start.as_micros() as u64,
+            "dur": end.saturating_sub(start).as_micros() as u64,
+            "pid": 1,
+            "tid": track,
+        }));
+    }
+
+    fn phase_events(&mut self, spans: Vec<Span>) {
+        for span in spans {
+            self.complete_event(PHASE_TRACK, "phase", &span.name, span.start, span.end);
+        }
+    }
+
+    /// Writes the pending line as ending at a point in time
+    fn line_event(&mut self, end: Duration) {
+        if let Some((index, start, text)) = self.pending.take() {
+            self.complete_event(index + 1, "line", &text, start, end);
+        }
+    }
+
+    fn write_event(&mut self, event: serde_json::Value) {
+        let separator = if self.events_written == 0 { "" } else { "," };
+        self.events_written += 1;
+        if let Err(error) = write!(self.output, "{separator}\n{event}") {
+            self.error.get_or_insert(error);
+        }
+    }
+}
+
+impl Observer for TraceWriter {
+    fn line(&mut self, line: &Line) -> Option<String> {
+        let index = match self.tracks.iter().position(|name| name == line.stream) {
+            Some(index) => index,
+            None => {
+                self.tracks.push(line.stream.to_string());
+                let name = if line.stream.is_empty() {
+                    "stdin"
+                } else {
+                    line.stream
+                };
+                self.track_name(self.tracks.len(), name);
+                self.tracks.len() - 1
+            }
+        };
+        self.line_event(line.time);
+        self.pending = Some((index, line.time, line.text.to_string()));
+
+        let (_, ended) = self.phases.line(line.time, line.text);
+        self.phase_events(ended);
+        None
+    }
+
+    fn end(&mut self, time: Duration, _output: &mut dyn Write) -> std::io::Result<()> {
+        self.line_event(time);
+        let ended = self.phases.end(time);
+        self.phase_events(ended);
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        writeln!(self.output, "\n]}}")?;
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SharedVec;
+
+    fn json(output: &SharedVec) -> serde_json::Value {
+        serde_json::from_str(&output.text()).unwrap()
+    }
+
+    fn line<'a>(millis: u64, stream: &'a str, text: &'a str) -> Line<'a> {
+        Line {
+            time: Duration::from_millis(millis),
+            stream,
+            text,
+        }
+    }
+
+    #[test]
+    fn lines_last_until_next_line_of_any_stream() {
+        let output = SharedVec::default();
+        let mut writer = TraceWriter::new(Box::new(output.clone()), vec![]).unwrap();
+        writer.line(&line(1000, "stdout", "a"));
+        writer.line(&line(1500, "stderr", "b"));
+        writer.line(&line(3000, "stdout", "c"));
+        writer
+            .end(Duration::from_millis(4000), &mut vec![])
+            .unwrap();
+
+        let events = json(&output)["traceEvents"].as_array().unwrap().clone();
+        let thread_names = events
+            .iter()
+            .filter(|event| event["ph"] == "M")
+            .map(|event| (event["tid"].clone(), event["args"]["name"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(1.into(), "stdout".into()), (2.into(), "stderr".into())],
+            thread_names
+        );
+        let lines = events
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap(),
+                    event["tid"].as_u64().unwrap(),
+                    event["ts"].as_u64().unwrap(),
+                    event["dur"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("a", 1, 1_000_000, 500_000),
+                ("b", 2, 1_500_000, 1_500_000),
+                ("c", 1, 3_000_000, 1_000_000)
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn phases_are_written_to_separate_track() {
+        let output = SharedVec::default();
+        let rules = vec!["build=^Build".parse().unwrap(), "^Step".parse().unwrap()];
+        let mut writer = TraceWriter::new(Box::new(output.clone()), rules).unwrap();
+        writer.line(&line(0, "", "Build"));
+        writer.line(&line(1000, "", "Step 1"));
+        writer.line(&line(2000, "", "Step 2"));
+        writer
+            .end(Duration::from_millis(3000), &mut vec![])
+            .unwrap();
+
+        let events = json(&output)["traceEvents"].as_array().unwrap().clone();
+        assert_eq!("phases", events[0]["args"]["name"]);
+        assert_eq!("stdin", events[1]["args"]["name"]);
+        let phases = events
+            .iter()
+            .filter(|event| event["cat"] == "phase")
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap(),
+                    event["ts"].as_u64().unwrap() / 1000,
+                    event["dur"].as_u64().unwrap() / 1000,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("Step 1", 1000, 1000),
+                ("Step 2", 2000, 1000),
+                ("build", 0, 3000)
+            ],
+            phases
+        );
+    }
+}
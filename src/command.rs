@@ -1,9 +1,32 @@
-use crate::error::{Result, ResultExt};
-use std::process::Stdio;
+pub mod fd;
+mod group;
+pub mod pipe;
+pub mod proc;
+pub mod processes;
+pub mod sample;
+pub mod usage;
 
+use crate::config;
+use crate::error::{MessageError, Result, ResultExt};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Time between checks if commands have exited
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Runs a command in its own process group, to kill and signal the command together with all
+/// processes it starts, e.g., all commands in a pipeline. When stdin is a terminal, the command
+/// stays in the foreground process group instead, to not be stopped when reading the terminal,
+/// and gets signals from the terminal directly.
 pub struct Runner {
     command: std::process::Command,
     child: Option<std::process::Child>,
+    new_group: bool,
+    // Ends of pipes to additional file descriptors given to the command, closed when spawned
+    output_fd_ends: Vec<std::fs::File>,
 }
 
 impl Runner {
@@ -16,17 +39,43 @@ impl Runner {
         Self {
             command,
             child: None,
+            new_group: !std::io::stdin().is_terminal(),
+            output_fd_ends: vec![],
         }
     }
 
+    /// Creates a runner executing a command line with `$SHELL -c`, or `/bin/sh -c` if not set
+    pub fn shell(command_line: &str) -> Self {
+        let shell = std::env::var("SHELL")
+            .ok()
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "/bin/sh".to_string());
+        Self::new(&[shell, "-c".to_string(), command_line.to_string()])
+    }
+
+    /// Opens a pipe the command writes to at an additional file descriptor number, returns the
+    /// end to read from
+    pub fn output_fd(&mut self, number: i32) -> Result<std::fs::File> {
+        assert!(self.child.is_none());
+        let (read_end, write_end) = fd::pipe_to(&mut self.command, number)?;
+        self.output_fd_ends.push(write_end);
+        Ok(read_end)
+    }
+
     /// Spawns a child process executing the command
     pub fn spawn(&mut self) -> Result<()> {
         assert!(self.child.is_none());
-        self.child = Some(
-            self.command
-                .spawn()
-                .error_context("Failed to execute command")?,
-        );
+        if self.new_group {
+            group::start_in_new_group(&mut self.command);
+        }
+        let spawned = self.command.spawn();
+        // Only the command is to hold the pipes open
+        self.output_fd_ends.clear();
+        let child = spawned.error_context("Failed to execute command")?;
+        if self.new_group {
+            group::register(child.id());
+        }
+        self.child = Some(child);
         Ok(())
     }
 
@@ -50,38 +99,307 @@ impl Runner {
             .expect("You can only get stderr for command once")
     }
 
-    /// Waits for the command to finish
-    pub fn wait(&mut self) {
+    /// Gets the process ID of the command
+    pub fn id(&self) -> u32 {
         assert!(self.child.is_some());
-        self.child
-            .as_mut()
-            .unwrap()
-            .wait()
-            .expect("Command expected to run");
+        self.child.as_ref().unwrap().id()
     }
 
-    /// Exits this program with the same status code as the command unless it was successful
-    pub fn exit_if_failed(&mut self) -> Result<()> {
+    /// Gets the exit status of the command if it has exited, without waiting
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
         assert!(self.child.is_some());
-        if let Some(status) = self
-            .child
-            .as_mut()
-            .unwrap()
+        let child = self.child.as_mut().unwrap();
+        let status = child
             .try_wait()
-            .error_context("Failed to get exit code for command")?
-        {
-            if !status.success() {
-                if let Some(code) = status.code() {
-                    eprintln!("Command exited with {code}");
-                    // Exit with same code as underlying program
-                    std::process::exit(code);
-                } else {
-                    eprintln!("Command terminated by signal");
+            .error_context("Failed to get exit code for command")?;
+        if status.is_some() && self.new_group {
+            group::reaped(child.id());
+        }
+        Ok(status)
+    }
+
+    /// Kills the command and all processes in its process group
+    pub fn kill(&mut self) {
+        assert!(self.child.is_some());
+        let child = self.child.as_mut().unwrap();
+        if self.new_group {
+            group::kill(child.id());
+        }
+        // An error means the command has already exited
+        let _ = child.kill();
+    }
+}
+
+/// A command to run together with other commands, with a label prefixing the names of its streams
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabeledCommand {
+    pub label: String,
+    /// Program and arguments, or a single command line when run by a shell
+    pub command: Vec<String>,
+    pub shell: bool,
+}
+
+impl LabeledCommand {
+    pub fn runner(&self) -> Runner {
+        if self.shell {
+            Runner::shell(&self.command.join(" "))
+        } else {
+            Runner::new(&self.command)
+        }
+    }
+}
+
+/// Commands given as `LABEL=COMMAND [ARGUMENT ...]`, several separated by `;;`. The label is
+/// optional and defaults to the name of the program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandList(pub Vec<LabeledCommand>);
+
+impl FromStr for LabeledCommand {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (label, command) = match s.split_once('=') {
+            Some((label, command))
+                if !label.is_empty()
+                    && label
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "_-.".contains(c)) =>
+            {
+                (Some(label.to_string()), command)
+            }
+            _ => (None, s),
+        };
+        let command = config::split(command)
+            .map_err(|error| MessageError(format!("Invalid command '{s}': {error}")))?;
+        let Some(program) = command.first() else {
+            return Err(MessageError(format!("No command given in '{s}'")));
+        };
+        let label = label.unwrap_or_else(|| {
+            Path::new(program)
+                .file_name()
+                .map_or(program.clone(), |name| name.to_string_lossy().into_owned())
+        });
+        Ok(Self {
+            label,
+            command,
+            shell: false,
+        })
+    }
+}
+
+impl FromStr for CommandList {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.split(";;")
+            .map(str::parse)
+            .collect::<std::result::Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Decides the exit code when running several commands
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ExitPolicy {
+    /// Exit when a command fails, with its exit code, after killing the other commands
+    #[default]
+    First,
+    /// Wait for all commands, and exit with the exit code of the first failed command
+    All,
+    /// Exit when the command with a label exits, with its exit code, after killing the other
+    /// commands
+    Primary(String),
+}
+
+impl FromStr for ExitPolicy {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "all" => Ok(Self::All),
+            _ => match s.strip_prefix("primary=") {
+                Some(label) if !label.is_empty() => Ok(Self::Primary(label.to_string())),
+                _ => Err(MessageError(format!(
+                    "Unknown exit policy '{s}', expected 'first', 'all' or 'primary=LABEL'"
+                ))),
+            },
+        }
+    }
+}
+
+impl ExitPolicy {
+    /// Checks if the exit of a command decides the exit code
+    fn decides(&self, label: &str, status: &ExitStatus) -> bool {
+        match self {
+            Self::First | Self::All => !status.success(),
+            Self::Primary(primary) => primary == label,
+        }
+    }
+}
+
+/// Waits for spawned commands to exit and returns the label and exit status of the command
+/// deciding the exit code, if any. The other commands are killed when the exit code is decided,
+/// unless waiting for all commands.
+pub fn supervise(
+    mut runners: Vec<(String, Runner)>,
+    policy: &ExitPolicy,
+) -> Result<Option<(String, ExitStatus)>> {
+    let mut deciding = None;
+    while !runners.is_empty() {
+        let mut index = 0;
+        while index < runners.len() {
+            let Some(status) = runners[index].1.try_wait()? else {
+                index += 1;
+                continue;
+            };
+            let (label, _) = runners.remove(index);
+            if deciding.is_none() && policy.decides(&label, &status) {
+                deciding = Some((label, status));
+                if *policy != ExitPolicy::All {
+                    runners.iter_mut().for_each(|(_, runner)| runner.kill());
                 }
             }
-        } else {
-            panic!("wait() should be called before exit_if_failed() on CommandRunner");
         }
-        Ok(())
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(deciding)
+}
+
+/// Kills the processes in the process groups of commands not yet reaped, or with processes left,
+/// e.g., started in the background by the commands
+pub fn kill_process_groups() {
+    group::kill_registered();
+}
+
+/// Exits this program with the exit code of a failed command. The label is left out when there
+/// is a single command.
+pub fn exit_if_failed(label: Option<&str>, status: &ExitStatus) {
+    if status.success() {
+        return;
+    }
+    let command = match label {
+        Some(label) => format!("Command '{label}'"),
+        None => "Command".to_string(),
+    };
+    if let Some(code) = status.code() {
+        eprintln!("{command} exited with {code}");
+        // Exit with same code as underlying program
+        std::process::exit(code);
+    } else {
+        eprintln!("{command} terminated by signal");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labeled(label: &str, command: &[&str]) -> LabeledCommand {
+        LabeledCommand {
+            label: label.to_string(),
+            command: command.iter().map(|s| s.to_string()).collect(),
+            shell: false,
+        }
+    }
+
+    #[test]
+    fn commands_are_parsed_with_and_without_labels() {
+        assert_eq!(
+            Ok(CommandList(vec![
+                labeled("web", &["npm", "run", "dev server"]),
+                labeled("cargo", &["/usr/bin/cargo", "test"]),
+            ])),
+            "web=npm run 'dev server' ;; /usr/bin/cargo test".parse()
+        );
+        assert_eq!(
+            Ok(labeled("env", &["env", "A=1"])),
+            "env A=1".parse::<LabeledCommand>()
+        );
+        assert!("web=".parse::<LabeledCommand>().is_err());
+    }
+
+    #[test]
+    fn exit_policies_are_parsed() {
+        assert_eq!(Ok(ExitPolicy::First), "first".parse());
+        assert_eq!(Ok(ExitPolicy::All), "all".parse());
+        assert_eq!(
+            Ok(ExitPolicy::Primary("tests".to_string())),
+            "primary=tests".parse()
+        );
+        assert!("primary=".parse::<ExitPolicy>().is_err());
+        assert!("last".parse::<ExitPolicy>().is_err());
+    }
+
+    /// Runs the command in its own process group also when the tests are run in a terminal
+    fn detached(mut runner: Runner) -> Runner {
+        runner.command.stdin(Stdio::null());
+        runner.new_group = true;
+        runner
+    }
+
+    fn spawn(label: &str, script: &str) -> (String, Runner) {
+        let mut runner = detached(Runner::new(&[
+            "sh".to_string(),
+            "-c".to_string(),
+            script.to_string(),
+        ]));
+        runner.spawn().unwrap();
+        (label.to_string(), runner)
+    }
+
+    fn code(deciding: Option<(String, ExitStatus)>) -> Option<(String, Option<i32>)> {
+        deciding.map(|(label, status)| (label, status.code()))
+    }
+
+    #[test]
+    fn first_failure_decides_exit_code_and_kills_other_commands() {
+        let start = std::time::Instant::now();
+        let runners = vec![spawn("slow", "sleep 10"), spawn("fail", "exit 3")];
+        assert_eq!(
+            Some(("fail".to_string(), Some(3))),
+            code(supervise(runners, &ExitPolicy::First).unwrap())
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn all_commands_are_waited_for() {
+        let runners = vec![
+            spawn("a", "sleep 0.2; exit 4"),
+            spawn("b", "exit 5"),
+            spawn("c", "exit 0"),
+        ];
+        assert_eq!(
+            Some(("b".to_string(), Some(5))),
+            code(supervise(runners, &ExitPolicy::All).unwrap())
+        );
+        let runners = vec![spawn("a", "exit 0"), spawn("b", "exit 0")];
+        assert_eq!(None, supervise(runners, &ExitPolicy::All).unwrap());
+    }
+
+    #[test]
+    fn killing_shell_kills_all_commands_in_pipeline() {
+        let start = std::time::Instant::now();
+        let mut runner = detached(Runner::shell("sleep 10 | sleep 10 && echo never"));
+        runner.spawn().unwrap();
+        let mut stdout = runner.stdout();
+        runner.kill();
+        let mut output = String::new();
+        // All processes holding the pipe are gone when reaching the end of it
+        std::io::Read::read_to_string(&mut stdout, &mut output).unwrap();
+        assert_eq!("", output);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn primary_command_decides_exit_code() {
+        let runners = vec![spawn("server", "sleep 10"), spawn("tests", "exit 0")];
+        let policy = ExitPolicy::Primary("tests".to_string());
+        assert_eq!(
+            Some(("tests".to_string(), Some(0))),
+            code(supervise(runners, &policy).unwrap())
+        );
     }
 }
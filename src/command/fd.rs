@@ -0,0 +1,146 @@
+use crate::error::{ErrorWithContext, MessageError, Result};
+use std::str::FromStr;
+
+/// Largest file descriptor number that can be given to commands. Pipes are moved above it before
+/// the command is executed.
+const MAX_NUMBER: i32 = 255;
+
+/// An additional file descriptor of executed commands, read as a stream with a name, e.g.,
+/// `3=progress`
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputFd {
+    pub number: i32,
+    pub name: String,
+}
+
+impl FromStr for OutputFd {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = |reason: &str| Err(MessageError(format!("Invalid fd '{s}', {reason}")));
+        let Some((number, name)) = s.split_once('=') else {
+            return invalid("expected N=NAME");
+        };
+        let Ok(number) = number.parse::<i32>() else {
+            return invalid("expected a number before =");
+        };
+        if number < 3 {
+            return invalid("stdin, stdout and stderr can't be used");
+        }
+        if number > MAX_NUMBER {
+            return invalid(&format!("the largest number is {MAX_NUMBER}"));
+        }
+        if name.is_empty() || name.contains('/') {
+            return invalid("expected a name without / after =");
+        }
+        Ok(Self {
+            number,
+            name: name.to_string(),
+        })
+    }
+}
+
+/// Opens a pipe that a command writes to at a file descriptor number. Returns the end to read
+/// from and the end given to the command, which is to be closed when the command is spawned, to
+/// get end of file when the command, and processes inheriting the descriptor, have exited.
+#[cfg(unix)]
+pub fn pipe_to(
+    command: &mut std::process::Command,
+    number: i32,
+) -> Result<(std::fs::File, std::fs::File)> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+
+    let error = |message: String| ErrorWithContext::wrap(message, std::io::Error::last_os_error());
+    let mut fds = [0; 2];
+    // SAFETY: pipe() only writes the two descriptors to the array
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(error(format!("Failed to open pipe for fd {number}")));
+    }
+    // SAFETY: The descriptors are open and owned by nothing else
+    let (read_end, write_end) =
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    // Both ends are closed when executing the command, and the end given is moved above the
+    // descriptor numbers, to not be overwritten when moving another pipe to its number
+    // SAFETY: fcntl() with F_SETFD and F_DUPFD_CLOEXEC only changes descriptors
+    let write_fd = unsafe {
+        libc::fcntl(read_end.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(write_end.as_raw_fd(), libc::F_DUPFD_CLOEXEC, MAX_NUMBER + 1)
+    };
+    if write_fd < 0 {
+        return Err(error(format!("Failed to open pipe for fd {number}")));
+    }
+    drop(write_end);
+    // SAFETY: The descriptor was just duplicated and is owned by nothing else
+    let write_end = unsafe { OwnedFd::from_raw_fd(write_fd) };
+    // SAFETY: dup2() is async-signal-safe, and the new descriptor is not closed on exec
+    unsafe {
+        command.pre_exec(move || {
+            if libc::dup2(write_fd, number) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok((
+        std::fs::File::from(read_end),
+        std::fs::File::from(write_end),
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn pipe_to(
+    _command: &mut std::process::Command,
+    number: i32,
+) -> Result<(std::fs::File, std::fs::File)> {
+    Err(ErrorWithContext::wrap(
+        format!("Failed to open pipe for fd {number}"),
+        std::io::Error::other("Additional file descriptors are only supported on Unix"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_fd_is_parsed() {
+        assert_eq!(
+            Ok(OutputFd {
+                number: 3,
+                name: "progress".to_string()
+            }),
+            "3=progress".parse()
+        );
+        assert_eq!(
+            Err(MessageError(
+                "Invalid fd '2=err', stdin, stdout and stderr can't be used".to_string()
+            )),
+            "2=err".parse::<OutputFd>()
+        );
+        assert!("progress".parse::<OutputFd>().is_err());
+        assert!("x=progress".parse::<OutputFd>().is_err());
+        assert!("3=".parse::<OutputFd>().is_err());
+        assert!("3=a/b".parse::<OutputFd>().is_err());
+        assert!("256=progress".parse::<OutputFd>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_writes_to_additional_fds() {
+        use std::io::Read;
+
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "echo three >&3; echo four >&4"]);
+        let (mut three, three_end) = pipe_to(&mut command, 3).unwrap();
+        let (mut four, four_end) = pipe_to(&mut command, 4).unwrap();
+        let mut child = command.spawn().unwrap();
+        drop((three_end, four_end));
+
+        let mut text = String::new();
+        three.read_to_string(&mut text).unwrap();
+        four.read_to_string(&mut text).unwrap();
+        assert_eq!("three\nfour\n", text);
+        child.wait().unwrap();
+    }
+}
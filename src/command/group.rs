@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Maximum number of process groups signals are forwarded to
+const MAX_GROUPS: usize = 64;
+
+/// Process groups of running commands, to forward signals to. Kept in atomics to be usable from
+/// the signal handler, 0 for unused slots.
+static GROUPS: [AtomicI32; MAX_GROUPS] = [const { AtomicI32::new(0) }; MAX_GROUPS];
+
+/// Makes a command start in a new process group, with the command as the group leader. The
+/// group gets all processes started by the command, e.g., all commands in a pipeline.
+pub fn start_in_new_group(command: &mut std::process::Command) {
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// Registers the process group of a started command, to forward termination signals to it.
+/// Termination signals are forwarded from when the first group is registered.
+pub fn register(leader: u32) {
+    let Ok(group) = i32::try_from(leader) else {
+        return;
+    };
+    if GROUPS.iter().any(|slot| {
+        slot.compare_exchange(0, group, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }) {
+        forward_termination_signals();
+    }
+}
+
+/// Unregisters the process group of a reaped command, unless processes are left in it, e.g.,
+/// started in the background. A group ID isn't reused while processes are left in the group.
+pub fn reaped(leader: u32) {
+    let Ok(group) = i32::try_from(leader) else {
+        return;
+    };
+    #[cfg(unix)]
+    {
+        // SAFETY: Signal 0 only checks if there are processes to send signals to
+        if unsafe { libc::killpg(group, 0) } == 0 {
+            return;
+        }
+    }
+    unregister(group);
+}
+
+/// Kills all processes in the registered process groups and unregisters them
+pub fn kill_registered() {
+    for slot in &GROUPS {
+        let group = slot.swap(0, Ordering::SeqCst);
+        if group > 0 {
+            kill(group as u32);
+        }
+    }
+}
+
+fn unregister(group: i32) {
+    for slot in &GROUPS {
+        let _ = slot.compare_exchange(group, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+/// Kills all processes in a process group
+pub fn kill(leader: u32) {
+    #[cfg(unix)]
+    if let Ok(group) = i32::try_from(leader) {
+        // SAFETY: Sending a signal has no memory safety implications
+        unsafe {
+            libc::killpg(group, libc::SIGKILL);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = leader;
+}
+
+/// Installs handlers forwarding SIGINT, SIGTERM and SIGHUP to the registered process groups.
+/// A command in its own process group doesn't get the signals sent to the foreground process
+/// group by the terminal, e.g., when pressing Ctrl+C. The command ending its output then ends
+/// linetime. A signal is only forwarded once, a second signal is handled as if no handler was
+/// installed, to end linetime even if a command ignores the signal. So is a signal when no group
+/// is registered, e.g., after all commands have exited.
+fn forward_termination_signals() {
+    #[cfg(unix)]
+    {
+        static INSTALLED: std::sync::Once = std::sync::Once::new();
+        INSTALLED.call_once(|| {
+            for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+                // SAFETY: The handler only uses async-signal-safe functions and atomics
+                unsafe {
+                    libc::signal(signal, forward as *const () as libc::sighandler_t);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn forward(signal: libc::c_int) {
+    let mut forwarded = false;
+    for slot in &GROUPS {
+        let group = slot.load(Ordering::SeqCst);
+        if group > 0 {
+            // SAFETY: kill() is async-signal-safe
+            unsafe {
+                libc::kill(-group, signal);
+            }
+            forwarded = true;
+        }
+    }
+    // SAFETY: signal() and raise() are async-signal-safe
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        if !forwarded {
+            libc::raise(signal);
+        }
+    }
+}
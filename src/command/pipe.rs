@@ -0,0 +1,100 @@
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time between checks if reading is to be stopped, while waiting for input
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Reads output of a command from a pipe until end of file, or until stopped. A pipe can be held
+/// open by a process started by the command, e.g., a daemon, after the command has exited.
+pub struct PipeReader<R> {
+    pipe: R,
+    stop: Arc<AtomicBool>,
+    // Set when the pipe has been made non-blocking, to only wait for input when there is none
+    non_blocking: bool,
+}
+
+impl<R> PipeReader<R> {
+    /// Creates a reader that ends when the flag is set
+    pub fn new(pipe: R, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            pipe,
+            stop,
+            non_blocking: false,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl<R: Read + std::os::fd::AsRawFd> Read for PipeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let fd = self.pipe.as_raw_fd();
+        if !self.non_blocking {
+            // SAFETY: fcntl() only changes the flags of the descriptor
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            self.non_blocking = true;
+        }
+        let mut poll_fd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            if self.stop.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            match self.pipe.read(buf) {
+                Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            // SAFETY: poll() only accesses the single pollfd passed
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, CHECK_INTERVAL.as_millis() as i32) };
+            if ready < 0 {
+                let error = std::io::Error::last_os_error();
+                if error.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl<R: Read> Read for PipeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.stop.load(Ordering::SeqCst) {
+            return Ok(0);
+        }
+        self.pipe.read(buf)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn reading_ends_when_stopped_while_pipe_is_open() {
+        let mut child = Command::new("sh")
+            .args(["-c", "echo hello; sleep 5"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut reader = PipeReader::new(child.stdout.take().unwrap(), stop.clone());
+
+        let mut buf = [0; 16];
+        assert_eq!(6, reader.read(&mut buf).unwrap());
+        stop.store(true, Ordering::SeqCst);
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+/// Status of a process, from `/proc/PID/stat`. Times are in clock ticks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stat {
+    pub pid: u32,
+    /// Name of the executable, possibly truncated
+    pub name: String,
+    /// State, e.g., `R` for running or `Z` for a zombie
+    pub state: char,
+    pub parent: u32,
+    /// CPU time of the process and of its terminated children that have been waited for
+    pub cpu_ticks: u64,
+    /// Time the process started after system boot
+    pub start_ticks: u64,
+    /// Resident set size in pages
+    pub rss_pages: u64,
+}
+
+impl Stat {
+    /// Parses the contents of a stat file. The name is within parentheses and can contain any
+    /// characters, the other fields are separated by spaces.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (pid, rest) = text.split_once(" (")?;
+        let (name, rest) = rest.rsplit_once(") ")?;
+        // Fields after the name, starting with field 3 in proc(5)
+        let fields = rest.split(' ').collect::<Vec<_>>();
+        let field = |number: usize| -> Option<u64> { fields.get(number - 3)?.parse().ok() };
+        Some(Self {
+            pid: pid.parse().ok()?,
+            name: name.to_string(),
+            state: fields.first()?.chars().next()?,
+            parent: u32::try_from(field(4)?).ok()?,
+            cpu_ticks: field(14)? + field(15)? + field(16)? + field(17)?,
+            start_ticks: field(22)?,
+            rss_pages: field(24)?,
+        })
+    }
+
+    /// Reads the status of a process, None if it doesn't exist
+    pub fn read(pid: u32) -> Option<Self> {
+        Self::parse(&std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?)
+    }
+
+    /// Checks if the process is still running, i.e., has not terminated as a zombie
+    pub fn is_alive(&self) -> bool {
+        self.state != 'Z' && self.state != 'X'
+    }
+}
+
+/// Gets the children of a process, from the children files of its threads
+pub fn children(pid: u32) -> Vec<u32> {
+    let Ok(tasks) = std::fs::read_dir(format!("/proc/{pid}/task")) else {
+        return vec![];
+    };
+    tasks
+        .flatten()
+        .filter_map(|task| std::fs::read_to_string(task.path().join("children")).ok())
+        .flat_map(|children| {
+            children
+                .split_whitespace()
+                .filter_map(|child| child.parse().ok())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Gets the status of processes and all their descendants
+pub fn tree(roots: &[u32]) -> Vec<Stat> {
+    let mut stats = vec![];
+    let mut pending = roots.to_vec();
+    while let Some(pid) = pending.pop() {
+        if let Some(stat) = Stat::read(pid) {
+            pending.extend(children(pid));
+            stats.push(stat);
+        }
+    }
+    stats
+}
+
+/// Gets the command line of a process, empty if not available, e.g., for a zombie
+pub fn command_line(pid: u32) -> Vec<String> {
+    std::fs::read(format!("/proc/{pid}/cmdline"))
+        .map(|bytes| {
+            bytes
+                .split(|&b| b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Gets the time since the system booted, in clock ticks like the start time of processes
+pub fn uptime_ticks(ticks_per_second: u64) -> Option<u64> {
+    let uptime = std::fs::read_to_string("/proc/uptime").ok()?;
+    let seconds = uptime.split_whitespace().next()?.parse::<f64>().ok()?;
+    Some((seconds * ticks_per_second as f64) as u64)
+}
+
+/// Gets the number of clock ticks per second and the page size in bytes
+pub fn units() -> (u64, u64) {
+    #[cfg(unix)]
+    {
+        // SAFETY: sysconf() only reads configuration values
+        let (ticks, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        (
+            u64::try_from(ticks).unwrap_or(100),
+            u64::try_from(page_size).unwrap_or(4096),
+        )
+    }
+    #[cfg(not(unix))]
+    (100, 4096)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_is_parsed_with_any_characters_in_name() {
+        let text = concat!(
+            "4242 (my (odd) cmd) S 4200 4242 4200 34817 4242 4194304 1517 0 0 0 ",
+            "150 25 10 5 20 0 1 0 987654 12345678 2048 18446744073709551615 ",
+            "1 1 0 0 0 0 0 0 0 0 0 0 17 3 0 0 0 0 0\n"
+        );
+        assert_eq!(
+            Some(Stat {
+                pid: 4242,
+                name: "my (odd) cmd".to_string(),
+                state: 'S',
+                parent: 4200,
+                cpu_ticks: 190,
+                start_ticks: 987654,
+                rss_pages: 2048,
+            }),
+            Stat::parse(text)
+        );
+        assert_eq!(None, Stat::parse("4242 (cmd) S 1"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn own_process_is_found_in_proc() {
+        let pid = std::process::id();
+        let stat = Stat::read(pid).unwrap();
+        assert_eq!(pid, stat.pid);
+        assert!(stat.is_alive());
+        assert_eq!(pid, tree(&[pid])[0].pid);
+        assert!(!command_line(pid).is_empty());
+        assert!(uptime_ticks(units().0).unwrap() >= stat.start_ticks);
+    }
+}
@@ -0,0 +1,196 @@
+use super::proc;
+use crate::output::timestamp;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Time between reading the process tree from `/proc`. Processes running shorter may be missed.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A descendant process seen in the process tree
+struct Tracked {
+    start_ticks: u64,
+    // Name of the executable, shown if the command line can't be read
+    name: String,
+    // Time running when first seen
+    age: Duration,
+    first_seen: Instant,
+    last_seen: Instant,
+    // Command line, set when the start has been reported
+    command: Option<String>,
+}
+
+impl Tracked {
+    fn duration(&self) -> Duration {
+        self.age + (self.last_seen - self.first_seen)
+    }
+}
+
+/// Stream of lines when descendant processes of commands start and exit, e.g.,
+/// `⚙ 4242 exited after 00:40.118: cc -c main.c`. A process is reported when it has run for the
+/// minimum duration, so short-lived processes can be ignored. The stream ends when all commands
+/// have exited.
+pub struct ProcessTracker {
+    roots: Vec<u32>,
+    min_duration: Duration,
+    microseconds: bool,
+    ticks_per_second: u64,
+    tracked: HashMap<u32, Tracked>,
+    lines: VecDeque<String>,
+    // Line not yet read completely
+    pending: Vec<u8>,
+}
+
+impl ProcessTracker {
+    pub fn new(roots: Vec<u32>, min_duration: Duration, microseconds: bool) -> Self {
+        Self {
+            roots,
+            min_duration,
+            microseconds,
+            ticks_per_second: proc::units().0,
+            tracked: HashMap::new(),
+            lines: VecDeque::new(),
+            pending: vec![],
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.roots
+            .iter()
+            .any(|&pid| proc::Stat::read(pid).is_some_and(|stat| stat.is_alive()))
+    }
+
+    /// Reads the process tree and queues lines for processes started or exited since last time
+    fn poll(&mut self) {
+        let now = Instant::now();
+        let uptime = proc::uptime_ticks(self.ticks_per_second);
+        for stat in proc::tree(&self.roots) {
+            if self.roots.contains(&stat.pid) || !stat.is_alive() {
+                continue;
+            }
+            if let Some(tracked) = self.tracked.get_mut(&stat.pid) {
+                if tracked.start_ticks == stat.start_ticks {
+                    continue;
+                }
+                // The process ID is reused by a new process, the tracked process has exited
+                let tracked = self.tracked.remove(&stat.pid).unwrap();
+                self.exited(stat.pid, tracked);
+            }
+            let age_ticks = uptime.map_or(0, |uptime| uptime.saturating_sub(stat.start_ticks));
+            self.tracked.insert(
+                stat.pid,
+                Tracked {
+                    start_ticks: stat.start_ticks,
+                    name: stat.name.clone(),
+                    age: Duration::from_secs_f64(age_ticks as f64 / self.ticks_per_second as f64),
+                    first_seen: now,
+                    last_seen: now,
+                    command: None,
+                },
+            );
+        }
+
+        // Processes are followed also when no longer in the tree, e.g., when their parent has
+        // exited
+        let mut exited = vec![];
+        for (&pid, tracked) in &mut self.tracked {
+            match proc::Stat::read(pid) {
+                Some(stat) if stat.start_ticks == tracked.start_ticks && stat.is_alive() => {
+                    tracked.last_seen = now;
+                }
+                _ => exited.push(pid),
+            }
+        }
+        exited.sort_unstable();
+        for pid in exited {
+            let tracked = self.tracked.remove(&pid).unwrap();
+            self.exited(pid, tracked);
+        }
+
+        let mut started = self
+            .tracked
+            .iter_mut()
+            .filter(|(_, tracked)| tracked.command.is_none())
+            .filter(|(_, tracked)| tracked.duration() >= self.min_duration)
+            .collect::<Vec<_>>();
+        started.sort_unstable_by_key(|(&pid, _)| pid);
+        for (&pid, tracked) in started {
+            // Read when reported, as a process started by fork() may not yet have executed the
+            // command when first seen
+            let mut command = proc::command_line(pid).join(" ");
+            if command.is_empty() {
+                command = format!("[{}]", tracked.name);
+            }
+            // Reported once running for the minimum duration, so with how long ago it started
+            let ago = timestamp::format(tracked.duration(), self.microseconds);
+            self.lines
+                .push_back(format!("\u{2699} {pid} started {ago} ago: {command}"));
+            tracked.command = Some(command);
+        }
+    }
+
+    fn exited(&mut self, pid: u32, tracked: Tracked) {
+        if let Some(command) = &tracked.command {
+            let duration = timestamp::format(tracked.duration(), self.microseconds);
+            self.lines
+                .push_back(format!("\u{2699} {pid} exited after {duration}: {command}"));
+        }
+    }
+
+    /// Waits for the next line, None if all commands have exited
+    fn next_line(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Some(line);
+            }
+            if !self.is_running() {
+                // Reports processes that exited together with the commands
+                self.poll();
+                return self.lines.pop_front();
+            }
+            std::thread::sleep(POLL_INTERVAL);
+            self.poll();
+        }
+    }
+}
+
+impl Read for ProcessTracker {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.next_line() {
+                Some(line) => self.pending = format!("{line}\n").into_bytes(),
+                None => return Ok(0),
+            }
+        }
+        let length = buf.len().min(self.pending.len());
+        buf[..length].copy_from_slice(&self.pending[..length]);
+        self.pending.drain(..length);
+        Ok(length)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn subprocesses_running_for_minimum_duration_are_reported() {
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 0.01; sleep 0.4; true"])
+            .spawn()
+            .unwrap();
+        let mut tracker = ProcessTracker::new(vec![child.id()], Duration::from_millis(200), false);
+        let waiter = std::thread::spawn(move || child.wait());
+
+        let mut output = String::new();
+        tracker.read_to_string(&mut output).unwrap();
+        waiter.join().unwrap().unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(2, lines.len(), "{output}");
+        assert!(lines[0].contains(" started 00:00."), "{output}");
+        assert!(lines[0].ends_with(" ago: sleep 0.4"), "{output}");
+        assert!(lines[1].contains(" exited after 00:00."), "{output}");
+        assert!(lines[1].ends_with(": sleep 0.4"), "{output}");
+    }
+}
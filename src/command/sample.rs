@@ -0,0 +1,128 @@
+use super::proc;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Time between checks if the sampled processes are still running, while waiting for the next
+/// sample
+const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Stream of lines with the resource usage of commands and their descendants, read from `/proc`
+/// at an interval, e.g., `CPU 187%, RSS 412.5 MiB, 9 processes`. The stream ends when all
+/// commands have exited.
+pub struct Sampler {
+    roots: Vec<u32>,
+    interval: Duration,
+    next_sample: Instant,
+    // Time and CPU ticks of the previous sample, to get the CPU usage since
+    previous: (Instant, u64),
+    ticks_per_second: u64,
+    page_size: u64,
+    // Sampled line not yet read completely
+    pending: Vec<u8>,
+}
+
+impl Sampler {
+    pub fn new(roots: Vec<u32>, interval: Duration) -> Self {
+        let (ticks_per_second, page_size) = proc::units();
+        let now = Instant::now();
+        let ticks = Self::cpu_ticks(&proc::tree(&roots));
+        Self {
+            roots,
+            interval,
+            next_sample: now + interval,
+            previous: (now, ticks),
+            ticks_per_second,
+            page_size,
+            pending: vec![],
+        }
+    }
+
+    fn cpu_ticks(stats: &[proc::Stat]) -> u64 {
+        stats.iter().map(|stat| stat.cpu_ticks).sum()
+    }
+
+    /// Checks if any of the commands is running, an exited command remains as a zombie until
+    /// waited for
+    fn is_running(&self) -> bool {
+        self.roots
+            .iter()
+            .any(|&pid| proc::Stat::read(pid).is_some_and(|stat| stat.is_alive()))
+    }
+
+    /// Waits for the next sample, None if all commands have exited
+    fn sample(&mut self) -> Option<String> {
+        loop {
+            if !self.is_running() {
+                return None;
+            }
+            let now = Instant::now();
+            if now >= self.next_sample {
+                break;
+            }
+            std::thread::sleep(CHECK_INTERVAL.min(self.next_sample - now));
+        }
+        let now = Instant::now();
+        // Skips samples missed, e.g., when the system is suspended
+        while self.next_sample <= now {
+            self.next_sample += self.interval;
+        }
+        let stats = proc::tree(&self.roots);
+        let ticks = Self::cpu_ticks(&stats);
+        let (previous_time, previous_ticks) = std::mem::replace(&mut self.previous, (now, ticks));
+        Some(format_sample(
+            ticks.saturating_sub(previous_ticks) as f64 / self.ticks_per_second as f64,
+            now - previous_time,
+            stats.iter().map(|stat| stat.rss_pages).sum::<u64>() * self.page_size,
+            stats.iter().filter(|stat| stat.is_alive()).count(),
+        ))
+    }
+}
+
+impl Read for Sampler {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.sample() {
+                Some(line) => self.pending = format!("{line}\n").into_bytes(),
+                None => return Ok(0),
+            }
+        }
+        let length = buf.len().min(self.pending.len());
+        buf[..length].copy_from_slice(&self.pending[..length]);
+        self.pending.drain(..length);
+        Ok(length)
+    }
+}
+
+/// Formats the CPU usage, as the share of a single CPU, and resident memory of processes
+fn format_sample(cpu_seconds: f64, elapsed: Duration, rss_bytes: u64, processes: usize) -> String {
+    let cpu = if elapsed.is_zero() {
+        0.0
+    } else {
+        100.0 * cpu_seconds / elapsed.as_secs_f64()
+    };
+    let processes = match processes {
+        1 => "1 process".to_string(),
+        count => format!("{count} processes"),
+    };
+    format!(
+        "CPU {cpu:.0}%, RSS {}, {processes}",
+        super::usage::format_bytes(rss_bytes)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_is_formatted_with_cpu_share_and_memory() {
+        assert_eq!(
+            "CPU 187%, RSS 412.5 MiB, 9 processes",
+            format_sample(3.74, Duration::from_secs(2), 432_537_600, 9)
+        );
+        assert_eq!(
+            "CPU 0%, RSS 4.0 KiB, 1 process",
+            format_sample(0.0, Duration::ZERO, 4096, 1)
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::output::timestamp;
+use std::io::Write;
+use std::time::Duration;
+
+/// Resources used by commands and their descendants that have been waited for
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResourceUsage {
+    pub user_cpu: Duration,
+    pub system_cpu: Duration,
+    /// Largest resident set size of a single process
+    pub max_rss_bytes: u64,
+    pub major_page_faults: u64,
+    pub minor_page_faults: u64,
+    pub voluntary_context_switches: u64,
+    pub involuntary_context_switches: u64,
+}
+
+impl ResourceUsage {
+    /// Gets the resources used by all child processes waited for, and their waited for
+    /// descendants. None if not available on the platform.
+    pub fn of_children() -> Option<Self> {
+        #[cfg(unix)]
+        {
+            // SAFETY: getrusage() only writes to the zeroed struct passed
+            let usage = unsafe {
+                let mut usage = std::mem::zeroed::<libc::rusage>();
+                if libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) != 0 {
+                    return None;
+                }
+                usage
+            };
+            let duration = |time: libc::timeval| {
+                Duration::from_secs(u64::try_from(time.tv_sec).unwrap_or_default())
+                    + Duration::from_micros(u64::try_from(time.tv_usec).unwrap_or_default())
+            };
+            let count = |value: libc::c_long| u64::try_from(value).unwrap_or_default();
+            // Max RSS is in bytes on macOS and in KiB on other systems
+            let rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+            Some(Self {
+                user_cpu: duration(usage.ru_utime),
+                system_cpu: duration(usage.ru_stime),
+                max_rss_bytes: count(usage.ru_maxrss) * rss_unit,
+                major_page_faults: count(usage.ru_majflt),
+                minor_page_faults: count(usage.ru_minflt),
+                voluntary_context_switches: count(usage.ru_nvcsw),
+                involuntary_context_switches: count(usage.ru_nivcsw),
+            })
+        }
+        #[cfg(not(unix))]
+        None
+    }
+
+    /// Writes a table of the resources used, with the CPU times formatted like timestamps
+    pub fn write(&self, output: &mut dyn Write, microseconds: bool) -> std::io::Result<()> {
+        let rows = [
+            ("User CPU", timestamp::format(self.user_cpu, microseconds)),
+            (
+                "System CPU",
+                timestamp::format(self.system_cpu, microseconds),
+            ),
+            ("Max RSS", format_bytes(self.max_rss_bytes)),
+            (
+                "Page faults",
+                format!(
+                    "{} major, {} minor",
+                    self.major_page_faults, self.minor_page_faults
+                ),
+            ),
+            (
+                "Context switches",
+                format!(
+                    "{} voluntary, {} involuntary",
+                    self.voluntary_context_switches, self.involuntary_context_switches
+                ),
+            ),
+        ];
+        let name_width = rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default();
+        for (name, value) in rows {
+            writeln!(output, "{name:<name_width$}  {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a number of bytes with a binary unit and one decimal, e.g., `412.5 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_are_formatted_with_binary_units() {
+        assert_eq!("512 B", format_bytes(512));
+        assert_eq!("1.0 KiB", format_bytes(1024));
+        assert_eq!("412.5 MiB", format_bytes(432_537_600));
+        assert_eq!("2048.0 TiB", format_bytes(1 << 51));
+    }
+
+    #[test]
+    fn usage_is_written_as_table() {
+        let usage = ResourceUsage {
+            user_cpu: Duration::from_millis(1500),
+            system_cpu: Duration::from_millis(250),
+            max_rss_bytes: 10 << 20,
+            major_page_faults: 2,
+            minor_page_faults: 3000,
+            voluntary_context_switches: 40,
+            involuntary_context_switches: 5,
+        };
+        let mut output = vec![];
+        usage.write(&mut output, false).unwrap();
+        assert_eq!(
+            concat!(
+                "User CPU          00:01.500\n",
+                "System CPU        00:00.250\n",
+                "Max RSS           10.0 MiB\n",
+                "Page faults       2 major, 3000 minor\n",
+                "Context switches  40 voluntary, 5 involuntary\n",
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
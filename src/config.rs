@@ -0,0 +1,323 @@
+use crate::error::{ErrorWithContext, MessageError, Result, ResultExt};
+use std::path::{Path, PathBuf};
+
+/// Environment variable with options, added after options from config files
+const OPTIONS_VARIABLE: &str = "LINETIME_OPTS";
+
+/// Name of the project config file, looked for in the current directory and its parents
+const PROJECT_FILE: &str = ".linetime.toml";
+
+/// Options allowed in a project config file, which may come with a repository that isn't trusted.
+/// Options executing commands, or writing or creating files, are not allowed.
+const PROJECT_OPTIONS: [&str; 28] = [
+    "show-delta",
+    "micros",
+    "lookahead",
+    "line-numbers",
+    "stream-line-numbers",
+    "include",
+    "exclude",
+    "context",
+    "shown-delta",
+    "show-control",
+    "show-escape",
+    "no-line-buffering",
+    "status",
+    "prefix-template",
+    "phase",
+    "profile",
+    "exit-policy",
+    "rusage",
+    "sample",
+    "processes",
+    "grace-period",
+    "fd",
+    "real-time",
+    "speed",
+    "format",
+    "mask",
+    "threshold",
+    "max-regression",
+];
+
+/// Default options from a config file, and named profiles with additional options. Keys are
+/// long option names, e.g., `show-delta = true` or `phase = ["build=^make", "test=^ctest"]`.
+/// Profiles are tables named `profile.NAME`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    options: Vec<String>,
+    profiles: Vec<(String, Vec<String>)>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> std::result::Result<Self, MessageError> {
+        Self::from_table(parse_table(text)?)
+    }
+
+    /// Parses a project config file, with only the options allowed in it
+    pub fn parse_project(text: &str) -> std::result::Result<Self, MessageError> {
+        let table = parse_table(text)?;
+        let profiles = table.get("profile").and_then(toml::Value::as_table);
+        let keys = table.keys().chain(
+            profiles
+                .into_iter()
+                .flat_map(toml::Table::values)
+                .filter_map(toml::Value::as_table)
+                .flat_map(toml::Table::keys),
+        );
+        for key in keys.filter(|key| *key != "profile") {
+            if !PROJECT_OPTIONS.contains(&key.replace('_', "-").as_str()) {
+                return Err(MessageError(format!(
+                    "'{key}' is not allowed in a project config file"
+                )));
+            }
+        }
+        Self::from_table(table)
+    }
+
+    fn from_table(mut table: toml::Table) -> std::result::Result<Self, MessageError> {
+        let profiles = match table.remove("profile") {
+            Some(toml::Value::Table(profiles)) => profiles
+                .iter()
+                .map(|(name, profile)| match profile {
+                    toml::Value::Table(profile) => Ok((name.clone(), options(profile)?)),
+                    _ => Err(MessageError(format!("Profile '{name}' should be a table"))),
+                })
+                .collect::<std::result::Result<_, _>>()?,
+            Some(_) => return Err(MessageError("'profile' should be a table".to_string())),
+            None => vec![],
+        };
+        Ok(Self {
+            options: options(&table)?,
+            profiles,
+        })
+    }
+
+    /// Loads a config file, if it exists, either a user's or a project's config file
+    fn load(path: &Path, project: bool) -> Result<Option<Self>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(ErrorWithContext::wrap(
+                    format!("Failed to read config file '{}'", path.display()),
+                    error,
+                ))
+            }
+        };
+        let config = if project {
+            Self::parse_project(&text)
+        } else {
+            Self::parse(&text)
+        };
+        config
+            .map(Some)
+            .error_context(format!("Invalid config file '{}'", path.display()))
+    }
+
+    fn profile(&self, name: &str) -> Option<&[String]> {
+        self.profiles
+            .iter()
+            .find(|(profile, _)| profile == name)
+            .map(|(_, options)| options.as_slice())
+    }
+}
+
+fn parse_table(text: &str) -> std::result::Result<toml::Table, MessageError> {
+    text.parse::<toml::Table>()
+        .map_err(|error| MessageError(error.to_string().trim_end().to_string()))
+}
+
+/// Converts the keys of a table to options
+fn options(table: &toml::Table) -> std::result::Result<Vec<String>, MessageError> {
+    let mut options = vec![];
+    for (key, value) in table {
+        let option = format!("--{}", key.replace('_', "-"));
+        let values = match value {
+            toml::Value::Boolean(true) => {
+                options.push(option);
+                continue;
+            }
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                _ => return Err(MessageError(format!("Unsupported value for '{key}'"))),
+            };
+            options.extend([option.clone(), value]);
+        }
+    }
+    Ok(options)
+}
+
+/// Gets the path of the user's config file, `$XDG_CONFIG_HOME/linetime/config.toml` or in
+/// `~/.config` if not set
+fn user_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("linetime").join("config.toml"))
+}
+
+/// Gets the path of the project config file in the current directory or nearest parent
+fn project_config_path() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    current_dir
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Gets options from the user's and the project's config files, with options of a named profile
+/// after the default options, and finally options from the `LINETIME_OPTS` environment
+/// variable. Command line options are to be added after these, to override them.
+pub fn options_before_command_line(profile_name: Option<&str>) -> Result<Vec<String>> {
+    let mut configs = vec![];
+    for (path, project) in [(user_config_path(), false), (project_config_path(), true)] {
+        if let Some(path) = path {
+            configs.extend(Config::load(&path, project)?);
+        }
+    }
+
+    let mut options = configs
+        .iter()
+        .flat_map(|config| config.options.iter().cloned())
+        .collect::<Vec<_>>();
+    if let Some(name) = profile_name {
+        let profiles = configs
+            .iter()
+            .filter_map(|config| config.profile(name))
+            .collect::<Vec<_>>();
+        if profiles.is_empty() {
+            return Err(ErrorWithContext::wrap(
+                format!("Unknown profile name '{name}'"),
+                MessageError("No config file has the profile".to_string()),
+            ));
+        }
+        options.extend(profiles.into_iter().flatten().cloned());
+    }
+    if let Ok(variable) = std::env::var(OPTIONS_VARIABLE) {
+        options.extend(split(&variable).error_context(format!("Invalid {OPTIONS_VARIABLE}"))?);
+    }
+    Ok(options)
+}
+
+/// Splits options, or a command and its arguments, at whitespace, except within single or double
+/// quotes
+pub fn split(text: &str) -> std::result::Result<Vec<String>, MessageError> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in text.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.get_or_insert_with(String::new).push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(MessageError("Unterminated quote".to_string()));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn keys_are_converted_to_options() {
+        let config = Config::parse(
+            r#"
+            show-delta = true
+            micros = false
+            context = 2
+            phase = ["build=^make", "test=^ctest"]
+            prefix_template = "{elapsed} "
+
+            [profile.ci]
+            include = "error"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            strings(&[
+                "--context",
+                "2",
+                "--phase",
+                "build=^make",
+                "--phase",
+                "test=^ctest",
+                "--prefix-template",
+                "{elapsed} ",
+                "--show-delta",
+            ]),
+            config.options
+        );
+        assert_eq!(
+            Some(strings(&["--include", "error"]).as_slice()),
+            config.profile("ci")
+        );
+        assert_eq!(None, config.profile("local"));
+    }
+
+    #[test]
+    fn invalid_config_is_reported() {
+        assert!(Config::parse("show-delta = ").is_err());
+        assert_eq!(
+            Err(MessageError("Unsupported value for 'phase'".to_string())),
+            Config::parse("phase = [true]")
+        );
+        assert_eq!(
+            Err(MessageError("Profile 'ci' should be a table".to_string())),
+            Config::parse("profile.ci = 1")
+        );
+    }
+
+    #[test]
+    fn project_config_can_not_execute_commands_or_write_files() {
+        let text = "show-delta = true\n[profile.ci]\ninclude = \"error\"\n";
+        assert_eq!(Config::parse(text), Config::parse_project(text));
+        for text in [
+            "cmd = \"make\"",
+            "shell = \"make\"",
+            "raw_log = \"out.log\"",
+            "profile.ci.record = \"ci.cast\"",
+        ] {
+            assert!(Config::parse(text).is_ok());
+            assert!(Config::parse_project(text).is_err(), "{text}");
+        }
+        assert_eq!(
+            Err(MessageError(
+                "'trace-out' is not allowed in a project config file".to_string()
+            )),
+            Config::parse_project("trace-out = \"trace.json\"")
+        );
+    }
+
+    #[test]
+    fn options_variable_is_split_at_whitespace_outside_quotes() {
+        assert_eq!(
+            strings(&["-d", "--include", "error|FAILED", "--phase", "a b=x"]),
+            split(" -d  --include 'error|FAILED' --phase \"a b=x\"").unwrap()
+        );
+        assert_eq!(strings(&[""]), split("''").unwrap());
+        assert!(split("'a").is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::analysis::{Line, Observer};
+use crate::error::Result;
+use crate::main_loop::MainLoop;
+use crate::output::{self, timestamp::Timestamp};
+use crate::record::Recording;
+use crate::replay::ReplayReader;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A line in a run, with its delta time from the previous line
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimedLine {
+    pub delta: Duration,
+    pub text: String,
+}
+
+/// Observer collecting the lines of a run
+#[derive(Clone, Default)]
+struct LineCollector {
+    lines: Arc<Mutex<Vec<TimedLine>>>,
+    previous: Option<Duration>,
+}
+
+impl Observer for LineCollector {
+    fn line(&mut self, line: &Line) -> Option<String> {
+        let delta = line.time.saturating_sub(self.previous.unwrap_or_default());
+        self.previous = Some(line.time);
+        if let Ok(mut lines) = self.lines.lock() {
+            lines.push(TimedLine {
+                delta,
+                text: line.text.to_string(),
+            });
+        }
+        None
+    }
+
+    fn end(&mut self, _time: Duration, _output: &mut dyn Write) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Gets the lines of a recorded run, unfolded the same way as when printing
+pub fn lines(recording: Recording) -> Result<Vec<TimedLine>> {
+    let collector = LineCollector::default();
+    let options = output::Options::default();
+    options.observers.add(collector.clone());
+
+    let end_time = recording.end_time();
+    let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+    let mut streams = ReplayReader::streams(recording.events, end_time, timestamp.clone(), None);
+    let mut outputs = streams.iter().map(|_| std::io::sink()).collect::<Vec<_>>();
+    let mut end_output = std::io::sink();
+    let mut main_loop = MainLoop::new(options, timestamp, &mut end_output);
+    for ((name, replay), output) in streams.iter_mut().zip(&mut outputs) {
+        main_loop.add_stream(replay, output, name);
+    }
+    main_loop.run()?;
+
+    let lines = collector
+        .lines
+        .lock()
+        .map(|lines| lines.clone())
+        .unwrap_or_default();
+    Ok(lines)
+}
+
+/// A line with a changed delta time between two runs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub text: String,
+    pub old: Duration,
+    pub new: Duration,
+}
+
+impl Change {
+    /// Gets the change of delta time in seconds, positive if the new run was slower
+    pub fn seconds(&self) -> f64 {
+        self.new.as_secs_f64() - self.old.as_secs_f64()
+    }
+}
+
+/// Normalizes the text of a line by replacing matches of the masks, e.g., for numbers or paths
+fn normalize(text: &str, masks: &[Regex]) -> String {
+    masks.iter().fold(text.trim().to_string(), |text, mask| {
+        mask.replace_all(text.as_str(), "\u{2022}").into_owned()
+    })
+}
+
+/// Compares the delta times of lines in two runs. Lines are aligned by normalized text, the n:th
+/// occurrence of a line in the old run is aligned with the n:th occurrence in the new run. Lines
+/// only in one of the runs are ignored. Changes larger than the threshold are returned, with
+/// the largest regression first and the largest improvement last.
+pub fn compare(
+    old: &[TimedLine],
+    new: &[TimedLine],
+    masks: &[Regex],
+    threshold: Duration,
+) -> Vec<Change> {
+    let mut old_deltas = HashMap::<String, Vec<Duration>>::new();
+    for line in old {
+        old_deltas
+            .entry(normalize(&line.text, masks))
+            .or_default()
+            .push(line.delta);
+    }
+    let mut occurrences = HashMap::<String, usize>::new();
+    let mut changes = vec![];
+    for line in new {
+        let text = normalize(&line.text, masks);
+        let occurrence = occurrences.entry(text.clone()).or_default();
+        if let Some(&old_delta) = old_deltas.get(&text).and_then(|d| d.get(*occurrence)) {
+            let change = Change {
+                text: line.text.clone(),
+                old: old_delta,
+                new: line.delta,
+            };
+            if change.seconds().abs() > threshold.as_secs_f64() {
+                changes.push(change);
+            }
+        }
+        *occurrence += 1;
+    }
+    changes.sort_by(|a, b| b.seconds().total_cmp(&a.seconds()));
+    changes
+}
+
+/// Writes a table with changes
+pub fn write_table(
+    changes: &[Change],
+    output: &mut impl Write,
+    microseconds: bool,
+) -> std::io::Result<()> {
+    let signed = |change: &Change| {
+        if change.new >= change.old {
+            format!(
+                "+{}",
+                output::timestamp::format(change.new - change.old, microseconds)
+            )
+        } else {
+            format!(
+                "-{}",
+                output::timestamp::format(change.old - change.new, microseconds)
+            )
+        }
+    };
+    let width = changes
+        .iter()
+        .flat_map(|change| {
+            [
+                signed(change).len(),
+                output::timestamp::format(change.old, microseconds).len(),
+                output::timestamp::format(change.new, microseconds).len(),
+            ]
+        })
+        .chain(["Change".len()])
+        .max()
+        .unwrap_or_default();
+
+    writeln!(
+        output,
+        "{:>width$}  {:>width$}  {:>width$}  Line",
+        "Change", "Old", "New"
+    )?;
+    for change in changes {
+        writeln!(
+            output,
+            "{:>width$}  {:>width$}  {:>width$}  {}",
+            signed(change),
+            output::timestamp::format(change.old, microseconds),
+            output::timestamp::format(change.new, microseconds),
+            change.text
+        )?;
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Event;
+
+    fn line(millis: u64, text: &str) -> TimedLine {
+        TimedLine {
+            delta: Duration::from_millis(millis),
+            text: text.to_string(),
+        }
+    }
+
+    fn change(text: &str, old: u64, new: u64) -> Change {
+        Change {
+            text: text.to_string(),
+            old: Duration::from_millis(old),
+            new: Duration::from_millis(new),
+        }
+    }
+
+    #[test]
+    fn recorded_output_is_unfolded_into_lines() {
+        let event = |millis, data: &str| Event {
+            time: Duration::from_millis(millis),
+            stream: String::new(),
+            data: data.to_string(),
+        };
+        let recording = Recording {
+            events: vec![event(1000, "a\n\x1b[31mb"), event(1500, "\rc\n")],
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![line(1000, "a"), line(0, "b"), line(500, "c")],
+            lines(recording).unwrap()
+        );
+    }
+
+    #[test]
+    fn lines_are_aligned_by_normalized_text_and_occurrence() {
+        let old = [
+            line(100, "Compiling foo v1.0"),
+            line(1000, "step"),
+            line(200, "step"),
+        ];
+        let new = [
+            line(100, "only in new"),
+            line(2000, "step"),
+            line(100, "step"),
+            line(400, "Compiling foo v1.1"),
+        ];
+        let masks = [Regex::new("[0-9]+").unwrap()];
+        assert_eq!(
+            vec![
+                change("step", 1000, 2000),
+                change("Compiling foo v1.1", 100, 400),
+                change("step", 200, 100)
+            ],
+            compare(&old, &new, &masks, Duration::ZERO)
+        );
+        assert_eq!(
+            vec![change("step", 1000, 2000)],
+            compare(&old, &new, &[], Duration::from_millis(500))
+        );
+        assert!(compare(&old, &new, &masks, Duration::from_secs(1)).is_empty());
+        let same = [line(100, "step")];
+        assert!(compare(&same, &same, &[], Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn changes_are_written_as_table() {
+        let mut output = vec![];
+        write_table(
+            &[change("slower", 100, 2100), change("faster", 300, 200)],
+            &mut output,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            concat!(
+                "    Change         Old         New  Line\n",
+                "+00:02.000   00:00.100   00:02.100  slower\n",
+                "-00:00.100   00:00.300   00:00.200  faster\n"
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
@@ -10,6 +10,18 @@ pub struct ErrorWithContext {
 /// Convenience short name
 pub type Result<T> = std::result::Result<T, ErrorWithContext>;
 
+/// Error with a message only, e.g., when an option value can't be parsed
+#[derive(Debug, PartialEq)]
+pub struct MessageError(pub String);
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for MessageError {}
+
 impl ErrorWithContext {
     pub fn wrap<E: std::error::Error + Send + 'static>(
         context: impl Into<String>,
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time between checks for new content, or a new file
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads what is appended to a file, like `tail -F`. Content already in the file when starting is
+/// skipped. The file is read from the start when it is truncated, or when replaced by another file
+/// with the same name, e.g., when a log file is rotated. Reading ends when stopped.
+pub struct FollowReader {
+    path: PathBuf,
+    file: Option<File>,
+    // Position in the file, to detect truncation
+    position: u64,
+    // Set until the file has been opened once, to skip the content when starting
+    starting: bool,
+    stop: Arc<AtomicBool>,
+}
+
+impl FollowReader {
+    pub fn new(path: impl Into<PathBuf>, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            path: path.into(),
+            file: None,
+            position: 0,
+            starting: true,
+            stop,
+        }
+    }
+
+    /// Opens the file, if it exists, at its end when starting, otherwise at the start. Returns
+    /// true if opened.
+    fn open(&mut self) -> std::io::Result<bool> {
+        let starting = std::mem::replace(&mut self.starting, false);
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        self.position = if starting {
+            file.seek(SeekFrom::End(0))?
+        } else {
+            0
+        };
+        self.file = Some(file);
+        Ok(true)
+    }
+
+    /// Checks if the file has been created, truncated or replaced, when all has been read.
+    /// Returns true if there may be content to read.
+    fn check_file(&mut self) -> std::io::Result<bool> {
+        let Some(file) = &mut self.file else {
+            return self.open();
+        };
+        let Ok(path_metadata) = std::fs::metadata(&self.path) else {
+            // Removed, the content of a new file is read from the start
+            self.file = None;
+            return Ok(false);
+        };
+        if !is_same_file(&file.metadata()?, &path_metadata) {
+            self.file = None;
+            return self.open();
+        }
+        if path_metadata.len() < self.position {
+            self.position = file.seek(SeekFrom::Start(0))?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &std::fs::Metadata, b: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &std::fs::Metadata, _b: &std::fs::Metadata) -> bool {
+    true
+}
+
+impl Read for FollowReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(file) = &mut self.file {
+                let length = file.read(buf)?;
+                if length > 0 {
+                    self.position += length as u64;
+                    return Ok(length);
+                }
+            }
+            if self.check_file()? {
+                continue;
+            }
+            if self.stop.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::io::Write;
+
+    fn read_available(reader: &mut FollowReader) -> String {
+        let mut buf = [0; 64];
+        let length = reader.read(&mut buf).unwrap();
+        String::from_utf8_lossy(&buf[..length]).into_owned()
+    }
+
+    #[test]
+    fn appended_content_is_read_after_truncation_and_rotation() {
+        let dir = TempDir::new("follow");
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut reader = FollowReader::new(&path, Arc::new(AtomicBool::new(false)));
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+
+        assert!(reader.open().unwrap());
+        file.write_all(b"new\n").unwrap();
+        assert_eq!("new\n", read_available(&mut reader));
+
+        file.set_len(0).unwrap();
+        file.write_all(b"cut\n").unwrap();
+        assert_eq!("cut\n", read_available(&mut reader));
+
+        std::fs::rename(&path, dir.path().join("app.log.1")).unwrap();
+        std::fs::write(&path, "rotated\n").unwrap();
+        assert_eq!("rotated\n", read_available(&mut reader));
+
+        let stop = Arc::new(AtomicBool::new(true));
+        let mut stopped = FollowReader::new(dir.path().join("missing.log"), stop);
+        assert_eq!("", read_available(&mut stopped));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::command::pipe::PipeReader;
+use crate::error::{ErrorWithContext, Result, ResultExt};
+use crate::main_loop::AddedStream;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+/// Start of a first line naming the stream of a connection, e.g., `#linetime label=tests`
+const HANDSHAKE: &[u8] = b"#linetime label=";
+
+/// Creates a stream to add from the name and input of a connection
+pub type StreamFactory = Arc<dyn Fn(String, Box<dyn Read + Send>) -> AddedStream + Send + Sync>;
+
+/// Where producers write lines, a Unix socket with a stream for each connection, or a named
+/// FIFO read as a single stream
+pub enum Listener {
+    #[cfg(unix)]
+    Socket(std::os::unix::net::UnixListener),
+    Fifo(PathBuf),
+}
+
+impl Listener {
+    /// Opens a FIFO if the path is one, otherwise creates a socket. A socket left by an earlier
+    /// run is replaced.
+    pub fn bind(path: &str) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            match std::fs::metadata(path) {
+                Ok(metadata) if metadata.file_type().is_fifo() => {
+                    return Ok(Self::Fifo(PathBuf::from(path)))
+                }
+                Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+                    .error_context(format!("Failed to replace socket '{path}'"))?,
+                Ok(_) => {
+                    return Err(ErrorWithContext::wrap(
+                        format!("Failed to listen on '{path}'"),
+                        std::io::Error::other("The file exists and is not a socket or FIFO"),
+                    ))
+                }
+                Err(_) => {}
+            }
+            let listener = std::os::unix::net::UnixListener::bind(path)
+                .error_context(format!("Failed to listen on '{path}'"))?;
+            Ok(Self::Socket(listener))
+        }
+        #[cfg(not(unix))]
+        Err(ErrorWithContext::wrap(
+            format!("Failed to listen on '{path}'"),
+            std::io::Error::other("Listening is only supported on Unix"),
+        ))
+    }
+
+    /// Sends a stream for each connection to the socket, or a single stream for the FIFO. Returns
+    /// when no more streams can be added, and the streams end, when stopped with `stop()`.
+    pub fn serve(
+        self,
+        streams: Sender<AddedStream>,
+        factory: StreamFactory,
+        stop: Arc<AtomicBool>,
+    ) {
+        match self {
+            #[cfg(unix)]
+            Self::Socket(listener) => {
+                for (index, connection) in listener.incoming().enumerate() {
+                    if stop.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    let Ok(connection) = connection else {
+                        continue;
+                    };
+                    let streams = streams.clone();
+                    let factory = factory.clone();
+                    let stop = stop.clone();
+                    // The name is read in another thread, to not wait for one producer to write
+                    // its first line before accepting others
+                    std::thread::spawn(move || {
+                        let default_name = peer_pid(&connection)
+                            .map_or(format!("conn-{}", index + 1), |pid| format!("pid-{pid}"));
+                        let mut connection = PipeReader::new(connection, stop);
+                        let Ok((name, first_bytes)) = handshake(&mut connection) else {
+                            return;
+                        };
+                        let input = std::io::Cursor::new(first_bytes).chain(connection);
+                        let name = name.unwrap_or(default_name);
+                        let _ = streams.send(factory(name, Box::new(input)));
+                    });
+                }
+            }
+            Self::Fifo(path) => {
+                let name = path
+                    .file_name()
+                    .map_or(path.to_string_lossy(), |name| name.to_string_lossy())
+                    .into_owned();
+                // Opened for writing too, to not be at end of file when writers close it, or wait
+                // for a writer to open it, so that reading only ends when stopped
+                let Ok(fifo) = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                else {
+                    return;
+                };
+                let _ = streams.send(factory(name, Box::new(PipeReader::new(fifo, stop))));
+            }
+        }
+    }
+}
+
+/// Stops listening, with the flag given to `Listener::serve()`. A connection is made to a socket,
+/// to not wait for a producer to connect.
+pub fn stop(path: &str, stop: &AtomicBool) {
+    stop.store(true, Ordering::SeqCst);
+    #[cfg(unix)]
+    let _ = std::os::unix::net::UnixStream::connect(path);
+}
+
+/// Removes a socket created when listening, a FIFO is left
+pub fn remove_socket(path: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)
+                .error_context(format!("Failed to remove socket '{path}'"))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Reads the start of a connection, to get the name from a first handshake line if given.
+/// Returns the name and the bytes read that are not part of a handshake.
+fn handshake(connection: &mut impl Read) -> std::io::Result<(Option<String>, Vec<u8>)> {
+    let mut bytes = vec![];
+    let mut buf = [0; 256];
+    // Reads until the handshake is complete, or the bytes read can't be the start of one
+    loop {
+        let is_handshake = bytes.starts_with(HANDSHAKE);
+        if is_handshake && bytes.contains(&b'\n') {
+            break;
+        }
+        if !is_handshake && !HANDSHAKE.starts_with(&bytes) {
+            return Ok((None, bytes));
+        }
+        let length = connection.read(&mut buf)?;
+        if length == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buf[..length]);
+    }
+    let Some(rest) = bytes.strip_prefix(HANDSHAKE) else {
+        return Ok((None, bytes));
+    };
+    let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+    let name = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+    let remaining = rest.get(end + 1..).unwrap_or_default().to_vec();
+    Ok(((!name.is_empty()).then_some(name), remaining))
+}
+
+/// Gets the process ID of the process connected to a socket
+#[cfg(target_os = "linux")]
+fn peer_pid(connection: &std::os::unix::net::UnixStream) -> Option<u32> {
+    use std::os::fd::AsRawFd;
+
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: getsockopt() only writes the credentials within the given length
+    let result = unsafe {
+        libc::getsockopt(
+            connection.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut credentials as *mut libc::ucred).cast(),
+            &mut length,
+        )
+    };
+    (result == 0 && credentials.pid > 0).then_some(credentials.pid as u32)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn peer_pid(_connection: &std::os::unix::net::UnixStream) -> Option<u32> {
+    None
+}
+
+/// Reads a FIFO from writers one after another. When all writers have closed the FIFO, it is
+/// opened again to wait for the next writer.
+pub struct FifoReader {
+    path: PathBuf,
+    fifo: Option<std::fs::File>,
+}
+
+impl FifoReader {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            fifo: None,
+        }
+    }
+}
+
+impl Read for FifoReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let fifo = match &mut self.fifo {
+                Some(fifo) => fifo,
+                // Blocks until a writer opens the FIFO
+                None => self.fifo.insert(std::fs::File::open(&self.path)?),
+            };
+            let length = fifo.read(buf)?;
+            if length > 0 {
+                return Ok(length);
+            }
+            self.fifo = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_line_names_stream() {
+        let mut input = "#linetime label=tests\nhello\n".as_bytes();
+        assert_eq!(
+            (Some("tests".to_string()), b"hello\n".to_vec()),
+            handshake(&mut input).unwrap()
+        );
+    }
+
+    #[test]
+    fn stream_without_handshake_is_kept() {
+        let mut input = "#line 1\n".as_bytes();
+        assert_eq!(
+            (None, b"#line 1\n".to_vec()),
+            handshake(&mut input).unwrap()
+        );
+        let mut input = "hello\n".as_bytes();
+        let (name, mut bytes) = handshake(&mut input).unwrap();
+        input.read_to_end(&mut bytes).unwrap();
+        assert_eq!((None, b"hello\n".to_vec()), (name, bytes));
+        let mut input = "#linetime label=\nhello\n".as_bytes();
+        assert_eq!((None, b"hello\n".to_vec()), handshake(&mut input).unwrap());
+    }
+}
@@ -1,15 +1,44 @@
+mod analysis;
 mod command;
+mod config;
+mod diff;
 mod error;
+mod follow;
+mod listen;
 mod main_loop;
+mod mark;
 mod output;
+mod parse;
+mod record;
+mod replay;
+mod signal;
+mod tee;
+#[cfg(test)]
+mod test_util;
 mod token;
 
-use crate::error::Result;
-use crate::main_loop::MainLoop;
+use crate::analysis::cargo::CargoProfile;
+use crate::analysis::phase::{PhaseRule, PhaseSummary};
+use crate::analysis::trace::TraceWriter;
+use crate::command::fd::OutputFd;
+use crate::command::pipe::PipeReader;
+use crate::command::{CommandList, ExitPolicy, LabeledCommand};
+use crate::error::{ErrorWithContext, MessageError, Result, ResultExt};
+use crate::follow::FollowReader;
+use crate::listen::Listener;
+use crate::main_loop::{AddedStream, MainLoop};
 use crate::output::buffered::LineWriteDecorator;
+use crate::output::status::StatusLine;
+use crate::output::timestamp::Timestamp;
+use crate::record::{Recorder, Recording};
+use crate::replay::ReplayReader;
+use crate::tee::{LogFiles, OutputFiles, Route, SharedWriter, Sink, TeeReader, TeeWriter};
 use gumdrop::{Options, ParsingStyle};
-use std::io::Write;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[derive(Debug, Options)]
 struct ProgramOptions {
@@ -22,15 +51,257 @@ struct ProgramOptions {
     )]
     micros: bool,
 
+    #[options(
+        no_short,
+        help = "hold each line until the next line to show the time until the next line"
+    )]
+    lookahead: bool,
+
+    #[options(short = "n", help = "show line numbers, common for all streams")]
+    line_numbers: bool,
+
+    #[options(short = "N", help = "show line numbers for each stream")]
+    stream_line_numbers: bool,
+
+    #[options(
+        no_short,
+        meta = "REGEX",
+        help = "only show lines matching a regex, deltas are still relative to all lines"
+    )]
+    include: Vec<regex::Regex>,
+
+    #[options(no_short, meta = "REGEX", help = "hide lines matching a regex")]
+    exclude: Vec<regex::Regex>,
+
+    #[options(
+        no_short,
+        meta = "N",
+        help = "show N lines before and after matching lines when filtering"
+    )]
+    context: Option<usize>,
+
+    #[options(
+        no_short,
+        help = "compute delta times over shown lines only when filtering"
+    )]
+    shown_delta: bool,
+
     #[options(short = "c", help = "show control characters as unicode symbols")]
     show_control: bool,
 
     #[options(short = "e", help = "show ANSI escape sequences")]
     show_escape: bool,
 
-    #[options(short = "l", help = "disable line buffering when executing command")]
+    #[options(
+        short = "l",
+        help = "disable line buffering of printed lines, logs are still written by line"
+    )]
     no_line_buffering: bool,
 
+    #[options(
+        no_short,
+        help = "show a status line with elapsed time, line counts and phase on stderr, if a terminal"
+    )]
+    status: bool,
+
+    #[options(
+        no_short,
+        meta = "TEMPLATE",
+        help = "template for line prefix, e.g., '{elapsed} {delta} [{stream:>6}] '"
+    )]
+    prefix_template: Option<output::template::Template>,
+
+    #[options(
+        no_short,
+        meta = "[STREAM=]FILE",
+        help = "write raw input to file, for all streams or only one, e.g., stderr=err.log"
+    )]
+    raw_log: Vec<String>,
+
+    #[options(
+        no_short,
+        meta = "FILE",
+        help = "write timestamped output also to file"
+    )]
+    timed_log: Vec<String>,
+
+    #[options(
+        no_short,
+        meta = "FILE",
+        help = "record input to an asciinema cast file"
+    )]
+    record: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "FILE",
+        help = "write a timeline of lines and phases in Chrome trace format"
+    )]
+    trace_out: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "[NAME=]REGEX",
+        help = "start a phase at lines matching a regex, later phases nest in earlier, durations are shown at the end"
+    )]
+    phase: Vec<PhaseRule>,
+
+    #[options(
+        no_short,
+        meta = "PROFILE",
+        help = "profile the output of a tool, cargo for time spent on each crate"
+    )]
+    profile: Option<analysis::Profile>,
+
+    #[options(
+        short = "s",
+        meta = "COMMAND_LINE",
+        help = "execute a command line with $SHELL -c, e.g., a pipeline"
+    )]
+    shell: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "[LABEL=]COMMAND",
+        help = "run a command, with streams prefixed by the label, several can be given or separated by ;;"
+    )]
+    cmd: Vec<CommandList>,
+
+    #[options(
+        no_short,
+        meta = "POLICY",
+        help = "exit code of several commands, first failure (first), all or primary=LABEL"
+    )]
+    exit_policy: Option<ExitPolicy>,
+
+    #[options(
+        no_short,
+        help = "show CPU time, max RSS, page faults and context switches of commands at the end"
+    )]
+    rusage: bool,
+
+    #[options(
+        no_short,
+        meta = "SECONDS",
+        help = "show CPU and memory usage of commands and their descendants at an interval"
+    )]
+    sample: Option<f64>,
+
+    #[options(
+        no_short,
+        meta = "MIN_SECONDS",
+        help = "show when descendant processes of commands running at least MIN_SECONDS start and exit"
+    )]
+    processes: Option<f64>,
+
+    #[options(
+        no_short,
+        meta = "SECONDS",
+        help = "time to wait for output to end after commands have exited, default 1 second"
+    )]
+    grace_period: Option<f64>,
+
+    #[options(
+        no_short,
+        meta = "N=NAME",
+        help = "read what commands write to file descriptor N as a stream, e.g., 3=progress"
+    )]
+    fd: Vec<OutputFd>,
+
+    #[options(
+        no_short,
+        meta = "SINK",
+        help = "write stdout of commands to stdout, stderr or a file"
+    )]
+    stdout_to: Option<Sink>,
+
+    #[options(
+        no_short,
+        meta = "SINK",
+        help = "write stderr of commands to stdout, stderr or a file, e.g., stdout to get all output in order on stdout"
+    )]
+    stderr_to: Option<Sink>,
+
+    #[options(
+        no_short,
+        meta = "STREAM=SINK",
+        help = "write a stream to stdout, stderr or a file, e.g., build/err=errors.log"
+    )]
+    route: Vec<Route>,
+
+    #[options(
+        no_short,
+        meta = "FILE",
+        help = "read lines appended to a file, like tail -F, instead of stdin or together with commands"
+    )]
+    follow: Vec<String>,
+
+    #[options(
+        no_short,
+        meta = "PATH",
+        help = "create a socket with a stream per connection, or read a FIFO, instead of stdin"
+    )]
+    listen: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "FIFO",
+        help = "write a mark line for each line written to a FIFO, created if missing"
+    )]
+    control: Option<String>,
+
+    #[options(short = "r", help = "replay with the recorded timing")]
+    real_time: bool,
+
+    #[options(
+        no_short,
+        meta = "FACTOR",
+        help = "replay with the recorded timing sped up by a factor"
+    )]
+    speed: Option<f64>,
+
+    #[options(
+        no_short,
+        meta = "FORMAT",
+        help = "output format when parsing, jsonl (default) or csv"
+    )]
+    format: Option<parse::Format>,
+
+    #[options(
+        no_short,
+        meta = "REGEX",
+        help = "mask matches of regex when aligning lines to diff, e.g., numbers or paths"
+    )]
+    mask: Vec<regex::Regex>,
+
+    #[options(
+        no_short,
+        meta = "SECONDS",
+        help = "change of delta time lines must exceed to diff, default 0.1 seconds"
+    )]
+    threshold: Option<f64>,
+
+    #[options(
+        no_short,
+        meta = "SECONDS",
+        help = "exit with code 2 if a line in diff is slower by more than this"
+    )]
+    max_regression: Option<f64>,
+
+    #[options(
+        no_short,
+        meta = "NAME",
+        help = "use options of a profile in config files, e.g., ci for [profile.ci]"
+    )]
+    profile_name: Option<String>,
+
+    #[options(
+        no_short,
+        meta = "OPTION",
+        help = "turn off a flag, or clear an option, set in config files or LINETIME_OPTS, e.g., show-delta"
+    )]
+    unset: Vec<String>,
+
     #[options(short = "h", help = "print help message and exit")]
     help: bool,
 
@@ -57,7 +328,10 @@ impl From<&ProgramOptions> for output::Options {
         Self {
             show_delta: options.show_delta,
             microseconds: options.micros,
+            line_numbers: options.line_numbers,
+            stream_line_numbers: options.stream_line_numbers,
             prefix: String::new(),
+            stream_width: 0,
             show_control: options.show_control,
             show_escape: options.show_escape,
             #[cfg(debug_assertions)]
@@ -68,6 +342,17 @@ impl From<&ProgramOptions> for output::Options {
             flush_all: options.flush_all,
             #[cfg(not(debug_assertions))]
             flush_all: false,
+            template: options.prefix_template.clone(),
+            observers: Default::default(),
+            lookahead: options.lookahead,
+            filter: (!options.include.is_empty() || !options.exclude.is_empty()).then(|| {
+                output::filter::Filter::new(
+                    options.include.clone(),
+                    options.exclude.clone(),
+                    options.context.unwrap_or_default(),
+                    options.shown_delta,
+                )
+            }),
         }
     }
 }
@@ -75,78 +360,1040 @@ impl From<&ProgramOptions> for output::Options {
 fn show_help(program_name: &str) {
     println!("Usage: {program_name} [option ...] command [argument ...]");
     println!("       {program_name} [option ...] -- command [argument ...]");
+    println!("       {program_name} [option ...] -s command-line");
+    println!("       {program_name} [option ...] --cmd [LABEL=]COMMAND ...");
+    println!("       {program_name} [option ...] --follow FILE ...");
+    println!("       {program_name} [option ...] --listen PATH");
     println!("       command [argument] | {program_name} [option ...]");
     println!("       command [argument] 2>&1 | {program_name} [option ...]");
+    println!("       {program_name} replay [option ...] recording");
+    println!("       {program_name} parse [--format FORMAT] [file]");
+    println!("       {program_name} diff [option ...] old new");
     println!();
     println!("Reads from stdin or executes a command and grabs its output. Each line is");
     println!("prefixed with a timestamp. Unfolding is attempted when escape sequences ");
     println!("overwrite the current line. When the command is executed, output is buffered");
     println!("to ensure lines written to stdout and stderr are not interleaved.");
     println!();
+    println!("Several commands can be executed side by side with --cmd. The streams of a");
+    println!("command are prefixed with its label, e.g., web/out and web/err. By default,");
+    println!("the other commands are killed when a command fails.");
+    println!();
+    println!("Streams of commands are written to stdout, except stderr, unless routed to");
+    println!("stdout, stderr or a file with --stdout-to, --stderr-to or --route.");
+    println!();
+    println!("With --listen, each connection to a Unix socket is a stream named by a first");
+    println!("line '#linetime label=NAME', or by the process ID of the producer. A named FIFO");
+    println!("is read as a single stream.");
+    println!();
+    println!("A mark line is written when linetime gets SIGUSR1, and for each line written");
+    println!("to the FIFO given with --control, with the line as text. With");
+    println!("--no-line-buffering, --control can't be used and marks may be written within");
+    println!("lines.");
+    println!();
+    println!("With --status, a status line with the elapsed time, the time since the last");
+    println!("line, line counts and the current phase is shown on stderr, if a terminal.");
+    println!();
+    println!("The CPU and memory usage of executed commands can be sampled to the sample");
+    println!("stream with --sample, and a summary of resources used shown with --rusage.");
+    println!("With --processes, subprocesses, e.g., compilers started by make, are shown");
+    println!("in the processes stream when they start and exit. As they are shown once");
+    println!("running for the minimum duration, the start line tells how long ago it was.");
+    println!();
+    println!("A recorded asciinema cast file can be replayed, with timestamps from the");
+    println!("recording, to process it again with other options. It is replayed as fast as");
+    println!("possible unless the recorded timing is requested. Linetime output, as text or");
+    println!("parsed, can also be replayed.");
+    println!();
+    println!("Linetime output with the default layout can be parsed, from a file or stdin,");
+    println!("into JSON lines or CSV with times in seconds.");
+    println!();
+    println!("Two recorded or saved runs can be compared with diff. Lines are aligned by");
+    println!("their text, with masked parts ignored, and lines with changed delta times are");
+    println!("listed with regressions first.");
+    println!();
+    println!("A phase starts at a line matching a phase regex and lasts until another phase");
+    println!("of the same or an outer level starts. Phases of later options nest within");
+    println!("phases of earlier options.");
+    println!();
+    println!("Lines can be filtered by regexes, matched against complete lines after");
+    println!("unfolding. Timestamps and line numbers are kept and the number of suppressed");
+    println!("lines is shown in the end line. Context lines are from the same stream as the");
+    println!("matching line and -- separates lines that are not adjacent.");
+    println!();
+    println!("Default options are read from $XDG_CONFIG_HOME/linetime/config.toml, or");
+    println!("~/.config/linetime/config.toml, and .linetime.toml in the current directory or");
+    println!("a parent. Keys are long option names, e.g., show-delta = true, and options of");
+    println!("a profile are in a [profile.NAME] table. A .linetime.toml can't have options");
+    println!("that execute commands or write files. Options in LINETIME_OPTS come after");
+    println!("the config files, and command line options replace them per option. Use");
+    println!("--unset OPTION to turn off a flag, or clear an option, from config files.");
+    println!();
+    println!("Lines unfolded from a single line get line numbers with a part number after a");
+    println!("dot, e.g., 17.2.");
+    println!();
+    println!("A prefix template can use the placeholders {{elapsed}}, {{delta}}, {{next}},");
+    println!(
+        "{{stream}}, {{lineno}} and {{stream_lineno}}. Alignment and width can be given, e.g.,"
+    );
+    println!(
+        "{{stream:<8}}, {{stream:>8}} or {{stream:^8}}. Use {{{{ and }}}} for literal braces and"
+    );
+    println!("\\t for a tab.");
+    println!();
     println!("{}", ProgramOptions::usage());
 }
 
+/// Gets the copies of raw input from a stream, to log files and recording
+fn raw_copies(logs: &LogFiles, recorder: &Option<Recorder>, stream: &str) -> Vec<SharedWriter> {
+    let mut copies = logs.raw(stream);
+    if let Some(recorder) = recorder {
+        copies.push(SharedWriter::new(recorder.clone()));
+    }
+    copies
+}
+
+/// Gets the output options, with observers analyzing the printed lines
+fn output_options(options: &ProgramOptions) -> Result<output::Options> {
+    let output_options = output::Options::from(options);
+    if !options.phase.is_empty() {
+        output_options
+            .observers
+            .add(PhaseSummary::new(options.phase.clone(), options.micros));
+    }
+    if options.profile == Some(analysis::Profile::Cargo) {
+        output_options
+            .observers
+            .add(CargoProfile::new(options.micros));
+    }
+    if let Some(path) = &options.trace_out {
+        output_options
+            .observers
+            .add(TraceWriter::create(path, options.phase.clone())?);
+    }
+    Ok(output_options)
+}
+
+/// Gets the commands to execute, a single command given as arguments or as a shell command
+/// line, or commands given with labels. A single command has no label.
+fn commands(options: &ProgramOptions) -> Result<Vec<LabeledCommand>> {
+    if !options.command.is_empty() {
+        return Ok(vec![LabeledCommand {
+            label: String::new(),
+            command: options.command.clone(),
+            shell: false,
+        }]);
+    }
+    if let Some(command_line) = &options.shell {
+        return Ok(vec![LabeledCommand {
+            label: String::new(),
+            command: vec![command_line.clone()],
+            shell: true,
+        }]);
+    }
+    let commands = options
+        .cmd
+        .iter()
+        .flat_map(|list| list.0.iter().cloned())
+        .collect::<Vec<_>>();
+    let invalid = |message: String| {
+        Err(ErrorWithContext::wrap(
+            "Invalid commands",
+            MessageError(message),
+        ))
+    };
+    for (index, command) in commands.iter().enumerate() {
+        if commands[..index]
+            .iter()
+            .any(|other| other.label == command.label)
+        {
+            return invalid(format!("The label '{}' is used twice", command.label));
+        }
+    }
+    if let Some(ExitPolicy::Primary(primary)) = &options.exit_policy {
+        if !commands.iter().any(|command| &command.label == primary) {
+            return invalid(format!("No command has the primary label '{primary}'"));
+        }
+    }
+    Ok(commands)
+}
+
+/// Gets the name of the stream with lines appended to a followed file, the name of the file
+fn follow_stream_name(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_name()
+        .map_or(path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// Gets the texts of marks to write, received from SIGUSR1 and the control FIFO
+fn marks(options: &ProgramOptions) -> Result<Receiver<String>> {
+    let (sender, marks) = std::sync::mpsc::channel();
+    mark::on_signal(sender.clone())?;
+    if let Some(path) = &options.control {
+        mark::read_control(path, sender)?;
+    }
+    Ok(marks)
+}
+
+/// Gets the status line, if requested and stderr is a terminal. It is drawn with the lock for
+/// output, so it can't be shown without line buffering.
+fn status_line(
+    options: &ProgramOptions,
+    output_options: &output::Options,
+    timestamp: &Arc<Mutex<Timestamp>>,
+    output_mutex: &Arc<Mutex<()>>,
+) -> Option<Arc<StatusLine>> {
+    use std::io::IsTerminal;
+
+    if !options.status || options.no_line_buffering || !std::io::stderr().is_terminal() {
+        return None;
+    }
+    let status = StatusLine::new(
+        timestamp.clone(),
+        output_mutex.clone(),
+        options.phase.clone(),
+        options.micros,
+    );
+    output_options.observers.add(status.observer());
+    Some(status)
+}
+
+/// Checks that additional file descriptors have unique numbers and stream names
+fn check_output_fds(fds: &[OutputFd]) -> Result<()> {
+    let invalid = |message: String| ErrorWithContext::wrap("Invalid fds", MessageError(message));
+    for (index, fd) in fds.iter().enumerate() {
+        let earlier = &fds[..index];
+        if earlier.iter().any(|other| other.number == fd.number) {
+            return Err(invalid(format!("The fd {} is given twice", fd.number)));
+        }
+        if earlier.iter().any(|other| other.name == fd.name)
+            || ["stdout", "stderr", "out", "err", "sample", "processes"].contains(&fd.name.as_str())
+        {
+            return Err(invalid(format!(
+                "The stream name '{}' is already used",
+                fd.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A stream read by the main loop, with the output its timestamped lines are written to and its name
+type Stream = (Box<dyn Read + Send>, Box<dyn Write + Send>, String);
+
+/// Outputs of streams, copied to the timed logs. Lines are written with a common lock, also used
+/// for marks and the status line, to not write lines from several streams at the same time.
+#[derive(Clone)]
+struct StreamOutputs {
+    mutex: Arc<Mutex<()>>,
+    status: Option<Arc<StatusLine>>,
+    timed: Vec<SharedWriter>,
+    line_buffering: bool,
+}
+
+impl StreamOutputs {
+    fn new(
+        options: &ProgramOptions,
+        output_options: &output::Options,
+        timestamp: &Arc<Mutex<Timestamp>>,
+        logs: &LogFiles,
+    ) -> Self {
+        let mutex = Arc::new(Mutex::new(()));
+        let status = status_line(options, output_options, timestamp, &mutex);
+        Self {
+            mutex,
+            status,
+            timed: logs.timed(),
+            line_buffering: !options.no_line_buffering,
+        }
+    }
+
+    /// Wraps the output of a stream, line buffered unless turned off. The copies to the timed
+    /// logs are always line buffered, to not mix lines of several streams in the logs.
+    fn wrap(&self, output: impl Write + Send + 'static) -> Box<dyn Write + Send> {
+        if self.line_buffering {
+            Box::new(
+                LineWriteDecorator::new(
+                    TeeWriter::new(output, self.timed.clone()),
+                    self.mutex.clone(),
+                )
+                .with_status(self.status.clone()),
+            )
+        } else {
+            let timed = self
+                .timed
+                .iter()
+                .map(|log| SharedWriter::new(LineWriteDecorator::new(log.clone(), Arc::default())))
+                .collect();
+            Box::new(TeeWriter::new(output, timed))
+        }
+    }
+}
+
+/// Runs the main loop reading streams, and streams added while running if given, with marks and
+/// the status line written to stdout, until all streams have ended
+fn run_streams(
+    outputs: &StreamOutputs,
+    output_options: output::Options,
+    timestamp: Arc<Mutex<Timestamp>>,
+    marks: Receiver<String>,
+    mut streams: Vec<Stream>,
+    added_streams: Option<Receiver<AddedStream>>,
+) -> Result<()> {
+    let mut mark_stdout = LineWriteDecorator::new(
+        TeeWriter::new(std::io::stdout(), outputs.timed.clone()),
+        outputs.mutex.clone(),
+    )
+    .with_status(outputs.status.clone());
+    let mut end_stdout = TeeWriter::new(std::io::stdout(), outputs.timed.clone());
+
+    let mut main_loop = MainLoop::new(output_options, timestamp, &mut end_stdout);
+    for (input, output, name) in &mut streams {
+        main_loop.add_stream(input.as_mut(), output.as_mut(), name);
+    }
+    if let Some(added_streams) = added_streams {
+        main_loop.add_streams_while_running(added_streams);
+    }
+    main_loop.add_marks(marks, &mut mark_stdout);
+    main_loop.show_status(outputs.status.clone());
+    main_loop.run()
+}
+
+/// Checks that followed files have unique stream names, as streams are named by the file names
+fn check_followed_files(paths: &[String]) -> Result<()> {
+    for (index, path) in paths.iter().enumerate() {
+        let name = follow_stream_name(path);
+        if let Some(other) = paths[..index]
+            .iter()
+            .find(|other| follow_stream_name(other) == name)
+        {
+            return Err(ErrorWithContext::wrap(
+                "Invalid files to follow",
+                MessageError(format!(
+                    "The files '{other}' and '{path}' have the same stream name '{name}'"
+                )),
+            ));
+        }
+    }
+    Ok(())
+}
+
 fn run_main_loop(options: &ProgramOptions) -> Result<()> {
-    if options.command.is_empty() {
-        let mut stdin = std::io::stdin();
-        let mut stdout = std::io::stdout();
+    let output_options = output_options(options)?;
+    let commands = commands(options)?;
+    check_output_fds(&options.fd)?;
+    check_followed_files(&options.follow)?;
+    if options.sample.is_some_and(|seconds| seconds <= 0.0) {
+        return Err(ErrorWithContext::wrap(
+            "Invalid sample interval",
+            MessageError("The interval must be greater than 0".to_string()),
+        ));
+    }
+    if options.grace_period.is_some_and(|seconds| seconds < 0.0) {
+        return Err(ErrorWithContext::wrap(
+            "Invalid grace period",
+            MessageError("The grace period must not be negative".to_string()),
+        ));
+    }
+    if options.processes.is_some_and(|seconds| seconds < 0.0) {
+        return Err(ErrorWithContext::wrap(
+            "Invalid minimum process duration",
+            MessageError("The duration must not be negative".to_string()),
+        ));
+    }
+    // Common Timestamp for all streams and recording
+    let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+    // Set for a single command, given as arguments or a shell command line
+    let unlabeled = commands.len() == 1 && commands[0].label.is_empty();
+    let recorded_command = if unlabeled {
+        commands[0].command.clone()
+    } else if commands.is_empty() {
+        vec![]
+    } else {
+        let command_lines = commands
+            .iter()
+            .map(|command| format!("{}={}", command.label, command.command.join(" ")))
+            .collect::<Vec<_>>();
+        vec![command_lines.join(" ;; ")]
+    };
+    let recorder = options
+        .record
+        .as_ref()
+        .map(|path| Recorder::create(path, timestamp.clone(), &recorded_command))
+        .transpose()?;
+    let marks = marks(options)?;
+    if let Some(path) = &options.listen {
+        if !commands.is_empty() || !options.follow.is_empty() {
+            return Err(ErrorWithContext::wrap(
+                "Invalid options",
+                MessageError("Commands and --follow can't be given with --listen".to_string()),
+            ));
+        }
+        let listener = Listener::bind(path)?;
+        // Stream names aren't known until producers connect, raw logs get all streams
+        let logs = Arc::new(LogFiles::open(
+            &mut OutputFiles::default(),
+            &options.raw_log,
+            &options.timed_log,
+            &[],
+        )?);
+        let outputs = StreamOutputs::new(options, &output_options, &timestamp, &logs);
+        let factory = Arc::new({
+            let (logs, recorder, outputs) = (logs.clone(), recorder.clone(), outputs.clone());
+            move |name: String, input: Box<dyn Read + Send>| AddedStream {
+                input: Box::new(TeeReader::new(input, raw_copies(&logs, &recorder, &name))),
+                output: outputs.wrap(std::io::stdout()),
+                name,
+            }
+        });
+        let (streams, added_streams) = std::sync::mpsc::channel();
+        // Connections are accepted until interrupted, when the streams end
+        let stop_listening = Arc::new(AtomicBool::new(false));
+        signal::on_interrupt({
+            let (path, stop) = (path.clone(), stop_listening.clone());
+            move || listen::stop(&path, &stop)
+        })?;
+        std::thread::spawn(move || listener.serve(streams, factory, stop_listening));
 
-        let mut main_loop = MainLoop::new(options.into());
-        main_loop.add_stream(&mut stdin, &mut stdout, "");
-        main_loop.run()?;
+        let streams = vec![];
+        let result = run_streams(
+            &outputs,
+            output_options,
+            timestamp,
+            marks,
+            streams,
+            Some(added_streams),
+        );
+        listen::remove_socket(path)?;
+        result?;
+        if let Some(recorder) = &recorder {
+            recorder.finish()?;
+        }
+    } else if commands.is_empty() && options.follow.is_empty() {
+        let logs = LogFiles::open(
+            &mut OutputFiles::default(),
+            &options.raw_log,
+            &options.timed_log,
+            &["stdin"],
+        )?;
+        let outputs = StreamOutputs::new(options, &output_options, &timestamp, &logs);
+        let stdin = TeeReader::new(std::io::stdin(), raw_copies(&logs, &recorder, "stdin"));
+
+        let streams: Vec<Stream> = vec![(
+            Box::new(stdin),
+            outputs.wrap(std::io::stdout()),
+            String::new(),
+        )];
+        run_streams(&outputs, output_options, timestamp, marks, streams, None)?;
+        if let Some(recorder) = &recorder {
+            recorder.finish()?;
+        }
+    } else if commands.is_empty() {
+        let stream_names = options
+            .follow
+            .iter()
+            .map(|path| follow_stream_name(path))
+            .collect::<Vec<_>>();
+        let stream_name_refs = stream_names.iter().map(String::as_str).collect::<Vec<_>>();
+        let logs = LogFiles::open(
+            &mut OutputFiles::default(),
+            &options.raw_log,
+            &options.timed_log,
+            &stream_name_refs,
+        )?;
+        let outputs = StreamOutputs::new(options, &output_options, &timestamp, &logs);
+        // Files are followed until interrupted, when the streams end
+        let stop_following = Arc::new(AtomicBool::new(false));
+        signal::on_interrupt({
+            let stop = stop_following.clone();
+            move || stop.store(true, Ordering::SeqCst)
+        })?;
+        let streams = options
+            .follow
+            .iter()
+            .zip(stream_names)
+            .map(|(path, name)| -> Stream {
+                let follower = FollowReader::new(path, stop_following.clone());
+                let copies = raw_copies(&logs, &recorder, &name);
+                (
+                    Box::new(TeeReader::new(follower, copies)),
+                    outputs.wrap(std::io::stdout()),
+                    name,
+                )
+            })
+            .collect();
+
+        run_streams(&outputs, output_options, timestamp, marks, streams, None)?;
+        if let Some(recorder) = &recorder {
+            recorder.finish()?;
+        }
     } else {
-        // Mutex to ensure not writing lines to stdout and stderr at the same time
-        let output_mutex = Arc::new(Mutex::new(()));
-        let mut stdout = std::io::stdout();
-        let mut stderr = std::io::stderr();
-        let mut wrapped_stdout = LineWriteDecorator::new(&mut stdout, output_mutex.clone());
-        let mut wrapped_stderr = LineWriteDecorator::new(&mut stderr, output_mutex);
-        let maybe_wrapped_stdout: &mut (dyn Write + Send) = if options.no_line_buffering {
-            &mut stdout
-        } else {
-            &mut wrapped_stdout
-        };
-        let maybe_wrapped_stderr: &mut (dyn Write + Send) = if options.no_line_buffering {
-            &mut stderr
-        } else {
-            &mut wrapped_stderr
-        };
+        // Streams are named after the label of their command, unless a single command is given.
+        // Each command's stdout, stderr and additional file descriptors are printed to stdout,
+        // except stderr, unless routed elsewhere.
+        let mut stream_names = vec![];
+        let mut sinks = vec![];
+        for command in &commands {
+            let (out, err, prefix) = if unlabeled {
+                ("stdout".to_string(), "stderr".to_string(), String::new())
+            } else {
+                let label = &command.label;
+                (
+                    format!("{label}/out"),
+                    format!("{label}/err"),
+                    format!("{label}/"),
+                )
+            };
+            stream_names.extend([out, err]);
+            sinks.extend([
+                options.stdout_to.clone().unwrap_or(Sink::Stdout),
+                options.stderr_to.clone().unwrap_or(Sink::Stderr),
+            ]);
+            for fd in &options.fd {
+                stream_names.push(format!("{prefix}{}", fd.name));
+                sinks.push(Sink::Stdout);
+            }
+        }
+        // Followed files are read until the commands have exited
+        for path in &options.follow {
+            stream_names.push(follow_stream_name(path));
+            sinks.push(Sink::Stdout);
+        }
+        // Streams monitoring the commands are printed to stdout after the streams of the
+        // commands, but not logged raw or recorded as they are not output of the commands
+        let command_streams = stream_names.len();
+        for (name, enabled) in [
+            ("sample", options.sample.is_some()),
+            ("processes", options.processes.is_some()),
+        ] {
+            if enabled {
+                stream_names.push(name.to_string());
+                sinks.push(Sink::Stdout);
+            }
+        }
+        for route in &options.route {
+            let Some(index) = stream_names.iter().position(|name| *name == route.stream) else {
+                return Err(ErrorWithContext::wrap(
+                    "Invalid route",
+                    MessageError(format!("Unknown stream '{}'", route.stream)),
+                ));
+            };
+            sinks[index] = route.sink.clone();
+        }
+        let mut files = OutputFiles::default();
+        let terminal_outputs = tee::open_sinks(&mut files, &sinks)?;
+        let stream_name_refs = stream_names.iter().map(String::as_str).collect::<Vec<_>>();
+        let logs = LogFiles::open(
+            &mut files,
+            &options.raw_log,
+            &options.timed_log,
+            &stream_name_refs,
+        )?;
 
-        let mut command = command::Runner::new(&options.command);
-        command.spawn()?;
-        let mut command_stdout = command.stdout();
-        let mut command_stderr = command.stderr();
+        let mut runners: Vec<(String, command::Runner)> = vec![];
+        // Set to stop reading output held open after the commands have exited
+        let stop_reading = Arc::new(AtomicBool::new(false));
+        let mut command_outputs: Vec<Box<dyn Read + Send>> = vec![];
+        for command in &commands {
+            let mut runner = command.runner();
+            let fd_outputs = options
+                .fd
+                .iter()
+                .map(|fd| runner.output_fd(fd.number))
+                .collect::<Result<Vec<_>>>()
+                .and_then(|fd_outputs| runner.spawn().map(|_| fd_outputs));
+            let fd_outputs = match fd_outputs {
+                Ok(fd_outputs) => fd_outputs,
+                Err(error) => {
+                    // Commands already started are not left running
+                    for (_, started) in &mut runners {
+                        started.kill();
+                    }
+                    return Err(error);
+                }
+            };
+            command_outputs.push(Box::new(PipeReader::new(
+                runner.stdout(),
+                stop_reading.clone(),
+            )));
+            command_outputs.push(Box::new(PipeReader::new(
+                runner.stderr(),
+                stop_reading.clone(),
+            )));
+            for fd_output in fd_outputs {
+                command_outputs.push(Box::new(PipeReader::new(fd_output, stop_reading.clone())));
+            }
+            runners.push((command.label.clone(), runner));
+        }
+        let stop_following = Arc::new(AtomicBool::new(false));
+        for path in &options.follow {
+            command_outputs.push(Box::new(FollowReader::new(path, stop_following.clone())));
+        }
+        let pids = runners
+            .iter()
+            .map(|(_, runner)| runner.id())
+            .collect::<Vec<_>>();
+        if let Some(seconds) = options.sample {
+            let interval = Duration::from_secs_f64(seconds);
+            command_outputs.push(Box::new(command::sample::Sampler::new(
+                pids.clone(),
+                interval,
+            )));
+        }
+        if let Some(seconds) = options.processes {
+            let min_duration = Duration::from_secs_f64(seconds);
+            command_outputs.push(Box::new(command::processes::ProcessTracker::new(
+                pids.clone(),
+                min_duration,
+                options.micros,
+            )));
+        }
+        let outputs = StreamOutputs::new(options, &output_options, &timestamp, &logs);
+        let streams = command_outputs
+            .into_iter()
+            .zip(terminal_outputs)
+            .zip(stream_names)
+            .enumerate()
+            .map(|(index, ((input, output), name))| -> Stream {
+                let copies = if index < command_streams {
+                    raw_copies(&logs, &recorder, &name)
+                } else {
+                    vec![]
+                };
+                (
+                    Box::new(TeeReader::new(input, copies)),
+                    outputs.wrap(output),
+                    name,
+                )
+            })
+            .collect();
 
-        let mut main_loop = MainLoop::new(options.into());
-        main_loop.add_stream(&mut command_stdout, maybe_wrapped_stdout, "stdout");
-        main_loop.add_stream(&mut command_stderr, maybe_wrapped_stderr, "stderr");
+        let policy = options.exit_policy.clone().unwrap_or_default();
+        let grace_period = Duration::from_secs_f64(options.grace_period.unwrap_or(1.0));
+        // Disconnected when all streams have ended
+        let (streams_ended, streams_running) = std::sync::mpsc::channel::<()>();
+        let supervisor = std::thread::spawn(move || {
+            let deciding = command::supervise(runners, &policy);
+            stop_following.store(true, Ordering::SeqCst);
+            // Output can be held open by processes started by the commands, e.g., in the
+            // background, which are killed if output hasn't ended after the grace period
+            if streams_running.recv_timeout(grace_period) == Err(RecvTimeoutError::Timeout) {
+                command::kill_process_groups();
+                stop_reading.store(true, Ordering::SeqCst);
+            }
+            deciding
+        });
+        let result = run_streams(&outputs, output_options, timestamp, marks, streams, None);
+        drop(streams_ended);
+        if let Err(error) = result {
+            // Commands are not left running, e.g., when output can't be written. Commands not in
+            // process groups of their own end when writing to the output closed on exit.
+            command::kill_process_groups();
+            return Err(error);
+        }
+        let deciding = supervisor
+            .join()
+            .expect("Thread waiting for commands unexpectedly panicked")
+            .inspect_err(|_| command::kill_process_groups())?;
+        if options.rusage {
+            if let Some(usage) = command::usage::ResourceUsage::of_children() {
+                let mut end_stdout = TeeWriter::new(std::io::stdout(), logs.timed());
+                usage
+                    .write(&mut end_stdout, options.micros)
+                    .and_then(|_| end_stdout.flush())
+                    .error_context("Error writing resource usage")?;
+            }
+        }
 
-        main_loop.run()?;
-        command.wait();
-        command.exit_if_failed()?;
+        if let Some(recorder) = &recorder {
+            let code = deciding
+                .as_ref()
+                .map_or(Some(0), |(_, status)| status.code());
+            if let Some(code) = code {
+                recorder.exit(code)?;
+            }
+            recorder.finish()?;
+        }
+        if let Some((label, status)) = deciding {
+            let label = (!unlabeled).then_some(label.as_str());
+            command::exit_if_failed(label, &status);
+        }
     };
     Ok(())
 }
 
-fn main() {
-    let args = std::env::args().collect::<Vec<String>>();
-    if let Ok(options) = ProgramOptions::parse_args(&args[1..], ParsingStyle::StopAtFirstFree) {
-        if options.help_requested() {
-            show_help(args[0].as_str());
-            return;
+fn run_replay(options: &ProgramOptions) -> Result<()> {
+    let speed = if options.real_time {
+        Some(1.0)
+    } else {
+        options.speed
+    };
+    if speed.is_some_and(|speed| speed <= 0.0) {
+        return Err(ErrorWithContext::wrap(
+            "Invalid replay speed",
+            MessageError("The factor must be greater than 0".to_string()),
+        ));
+    }
+    let output_options = output_options(options)?;
+    let recording = Recording::load(&options.command[0])?;
+    let end_time = recording.end_time();
+
+    let logs = LogFiles::open(&mut OutputFiles::default(), &[], &options.timed_log, &[])?;
+    let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+    let outputs = StreamOutputs::new(options, &output_options, &timestamp, &logs);
+    // Each recorded stream is replayed as its own stream
+    let mut streams = ReplayReader::streams(recording.events, end_time, timestamp.clone(), speed)
+        .into_iter()
+        .map(|(name, replay)| -> Stream {
+            (Box::new(replay), outputs.wrap(std::io::stdout()), name)
+        })
+        .collect::<Vec<_>>();
+    let mut end_stdout = TeeWriter::new(std::io::stdout(), logs.timed());
+
+    let mut main_loop = MainLoop::new(output_options, timestamp, &mut end_stdout);
+    for (input, output, name) in &mut streams {
+        main_loop.add_stream(input.as_mut(), output.as_mut(), name);
+    }
+    main_loop.run()?;
+
+    if let Some((_, code)) = recording.exit {
+        if code != 0 {
+            eprintln!("Command exited with {code}");
+            std::process::exit(code);
+        }
+    }
+    Ok(())
+}
+
+fn run_parse(options: &ProgramOptions) -> Result<()> {
+    let format = options.format.unwrap_or_default();
+    // Summaries are written to stderr, to keep the parsed lines on stdout readable by tools
+    let observers = output_options(options)?.observers;
+    let mut stdout = std::io::stdout().lock();
+    let mut stderr = std::io::stderr();
+    let result = match options.command.first() {
+        Some(path) => {
+            let file =
+                std::fs::File::open(path).error_context(format!("Failed to open file '{path}'"))?;
+            let input = std::io::BufReader::new(file);
+            parse::convert(input, &mut stdout, format, &observers, &mut stderr)
+        }
+        None => parse::convert(
+            std::io::stdin().lock(),
+            &mut stdout,
+            format,
+            &observers,
+            &mut stderr,
+        ),
+    };
+    result.error_context("Failed to parse linetime output")
+}
+
+fn run_diff(options: &ProgramOptions) -> Result<()> {
+    let seconds = |name: &str, value: Option<f64>, default: f64| {
+        let value = value.unwrap_or(default);
+        Duration::try_from_secs_f64(value)
+            .map_err(|error| ErrorWithContext::wrap(format!("Invalid {name} '{value}'"), error))
+    };
+    let threshold = seconds("threshold", options.threshold, 0.1)?;
+    let max_regression = options
+        .max_regression
+        .map(|limit| seconds("regression limit", Some(limit), 0.0))
+        .transpose()?;
+
+    let old = diff::lines(Recording::load(&options.command[0])?)?;
+    let new = diff::lines(Recording::load(&options.command[1])?)?;
+    let changes = diff::compare(&old, &new, &options.mask, threshold);
+    diff::write_table(&changes, &mut std::io::stdout(), options.micros)
+        .error_context("Error writing to stdout")?;
+
+    if let Some(limit) = max_regression {
+        if changes
+            .first()
+            .is_some_and(|change| change.seconds() > limit.as_secs_f64())
+        {
+            eprintln!("Regression limit exceeded");
+            std::process::exit(2);
         }
-        if options.version {
-            println!("linetime version {}", env!("CARGO_PKG_VERSION"));
-            return;
+    }
+    Ok(())
+}
+
+/// Checks that options given on the command line apply to the mode, e.g., --speed only applies
+/// to replay. Options from config files are defaults for all modes, and are not checked.
+fn check_mode_options(options: &ProgramOptions, subcommand: Option<&str>) -> Result<()> {
+    let replay = subcommand == Some("replay");
+    let parse = subcommand == Some("parse");
+    let diff = subcommand == Some("diff");
+    // Diff compares lines of runs with their times, and doesn't analyze them
+    let analyzed = !diff;
+    let only_replay = "It can only be used with replay";
+    let only_parse = "It can only be used with parse";
+    let only_diff = "It can only be used with diff";
+    let not_diff = "It can't be used with diff";
+    // Options for reading streams live, which subcommands reading saved output don't do
+    let live = subcommand.is_none();
+    let not_live = format!("It can't be used with {}", subcommand.unwrap_or_default());
+    let filtered = !options.include.is_empty() || !options.exclude.is_empty();
+    let has_command = subcommand.is_none()
+        && (!options.command.is_empty() || options.shell.is_some() || !options.cmd.is_empty());
+    let only_command = "It can only be used when executing a command";
+    let mode_options = [
+        (
+            "--context",
+            options.context.is_some(),
+            filtered,
+            "It can only be used with --include or --exclude",
+        ),
+        ("--rusage", options.rusage, has_command, only_command),
+        (
+            "--grace-period",
+            options.grace_period.is_some(),
+            has_command,
+            only_command,
+        ),
+        (
+            "--exit-policy",
+            options.exit_policy.is_some(),
+            has_command,
+            only_command,
+        ),
+        ("--fd", !options.fd.is_empty(), has_command, only_command),
+        (
+            "--processes",
+            options.processes.is_some(),
+            has_command,
+            only_command,
+        ),
+        (
+            "--sample",
+            options.sample.is_some(),
+            has_command,
+            only_command,
+        ),
+        (
+            "--stdout-to",
+            options.stdout_to.is_some(),
+            has_command,
+            only_command,
+        ),
+        (
+            "--stderr-to",
+            options.stderr_to.is_some(),
+            has_command,
+            only_command,
+        ),
+        (
+            "--route",
+            !options.route.is_empty(),
+            has_command,
+            only_command,
+        ),
+        ("--listen", options.listen.is_some(), live, &not_live),
+        ("--follow", !options.follow.is_empty(), live, &not_live),
+        ("--record", options.record.is_some(), live, &not_live),
+        ("--raw-log", !options.raw_log.is_empty(), live, &not_live),
+        ("--status", options.status, live, &not_live),
+        ("--real-time", options.real_time, replay, only_replay),
+        ("--speed", options.speed.is_some(), replay, only_replay),
+        ("--format", options.format.is_some(), parse, only_parse),
+        ("--mask", !options.mask.is_empty(), diff, only_diff),
+        ("--threshold", options.threshold.is_some(), diff, only_diff),
+        (
+            "--max-regression",
+            options.max_regression.is_some(),
+            diff,
+            only_diff,
+        ),
+        ("--phase", !options.phase.is_empty(), analyzed, not_diff),
+        ("--profile", options.profile.is_some(), analyzed, not_diff),
+        (
+            "--trace-out",
+            options.trace_out.is_some(),
+            analyzed,
+            not_diff,
+        ),
+    ];
+    match mode_options
+        .iter()
+        .find(|(_, given, applies, _)| *given && !applies)
+    {
+        Some((name, _, _, reason)) => Err(ErrorWithContext::wrap(
+            format!("Invalid option {name}"),
+            MessageError(reason.to_string()),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Checks options that can't be combined with each other, after options from config files and
+/// the environment are added, as either option can be given there
+fn check_combined_options(options: &ProgramOptions) -> Result<()> {
+    if options.control.is_some() && options.no_line_buffering {
+        return Err(ErrorWithContext::wrap(
+            "Invalid option --control",
+            MessageError("It can't be used with --no-line-buffering".to_string()),
+        ));
+    }
+    Ok(())
+}
+
+/// Defines how options from config files and the environment are combined with options given
+/// on the command line. Flags are turned on by either, and other options given on the command line
+/// replace the options from config files, also options that can be given several times. Options
+/// not listed can only be given on the command line.
+macro_rules! config_options {
+    (flags: [$($flag:ident),*], values: [$($value:ident),*], lists: [$($list:ident),*]) => {
+        /// Turns off a flag, or clears an option, from config files, given by its long name
+        fn unset_config_option(config: &mut ProgramOptions, name: &str) -> Result<()> {
+            match name.replace('-', "_").as_str() {
+                $(stringify!($flag) => config.$flag = false,)*
+                $(stringify!($value) => config.$value = None,)*
+                $(stringify!($list) => config.$list.clear(),)*
+                _ => {
+                    return Err(ErrorWithContext::wrap(
+                        format!("Invalid option to unset '{name}'"),
+                        MessageError("It can't be set in config files".to_string()),
+                    ))
+                }
+            }
+            Ok(())
         }
 
-        if let Err(error) = run_main_loop(&options) {
-            eprintln!("{error}");
+        /// Takes the options given on the command line, and the other options from config files
+        fn merge_config_options(options: &mut ProgramOptions, config: ProgramOptions) {
+            $(options.$flag |= config.$flag;)*
+            $(
+                if options.$value.is_none() {
+                    options.$value = config.$value;
+                }
+            )*
+            $(
+                if options.$list.is_empty() {
+                    options.$list = config.$list;
+                }
+            )*
+        }
+    };
+}
+
+config_options! {
+    flags: [
+        show_delta, micros, lookahead, line_numbers, stream_line_numbers, shown_delta,
+        show_control, show_escape, no_line_buffering, status, rusage, real_time
+    ],
+    values: [
+        context, prefix_template, record, trace_out, profile, shell, exit_policy, sample,
+        processes, grace_period, stdout_to, stderr_to, listen, control, speed, format, threshold,
+        max_regression
+    ],
+    lists: [include, exclude, raw_log, timed_log, phase, cmd, fd, route, follow, mask]
+}
+
+/// Adds options from config files and the environment that aren't given on the command line
+fn with_config_options(
+    mut options: ProgramOptions,
+    subcommand: Option<&str>,
+) -> Result<ProgramOptions> {
+    let config_args = config::options_before_command_line(options.profile_name.as_deref())?;
+    // Parsed alone to not take a value in a config for the command to execute
+    let mut config_options = ProgramOptions::parse_args(&config_args, ParsingStyle::AllOptions)
+        .error_context("Invalid options in config files or LINETIME_OPTS")?;
+    if let Some(argument) = config_options.command.first() {
+        return Err(ErrorWithContext::wrap(
+            "Invalid options in config files or LINETIME_OPTS",
+            MessageError(format!("Unexpected argument '{argument}'")),
+        ));
+    }
+    for name in &options.unset {
+        unset_config_option(&mut config_options, name)?;
+    }
+    // Commands given on the command line, in any way, replace commands from config files, which
+    // also don't apply to subcommands
+    if subcommand.is_some()
+        || !options.command.is_empty()
+        || options.shell.is_some()
+        || !options.cmd.is_empty()
+    {
+        config_options.shell = None;
+        config_options.cmd.clear();
+    }
+    merge_config_options(&mut options, config_options);
+    Ok(options)
+}
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    // Subcommand given as first argument, use "--" before a command with the same name
+    let subcommand = args
+        .get(1)
+        .map(String::as_str)
+        .filter(|arg| ["replay", "parse", "diff"].contains(arg));
+    let first_option = if subcommand.is_some() { 2 } else { 1 };
+    match ProgramOptions::parse_args(&args[first_option..], ParsingStyle::StopAtFirstFree) {
+        Ok(options) => {
+            if options.help_requested() {
+                show_help(args[0].as_str());
+                return;
+            }
+            if options.version {
+                println!("linetime version {}", env!("CARGO_PKG_VERSION"));
+                return;
+            }
+            if let Err(error) = check_mode_options(&options, subcommand) {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+            let options = match with_config_options(options, subcommand)
+                .and_then(|options| check_combined_options(&options).map(|_| options))
+            {
+                Ok(options) => options,
+                Err(error) => {
+                    eprintln!("{error}");
+                    std::process::exit(1);
+                }
+            };
+            let command_sources = [
+                !options.cmd.is_empty(),
+                options.shell.is_some(),
+                subcommand.is_some() || !options.command.is_empty(),
+            ];
+            if command_sources.iter().filter(|&&given| given).count() > 1 {
+                show_help(args[0].as_str());
+                eprintln!("\nCommands should be given either with --cmd, --shell or as arguments");
+                std::process::exit(1);
+            }
+            if subcommand == Some("replay") && options.command.len() != 1 {
+                show_help(args[0].as_str());
+                eprintln!("\nA single recording file should be given to replay");
+                std::process::exit(1);
+            }
+            if subcommand == Some("diff") && options.command.len() != 2 {
+                show_help(args[0].as_str());
+                eprintln!("\nAn old and a new run should be given to diff");
+                std::process::exit(1);
+            }
+            if subcommand == Some("parse") && options.command.len() > 1 {
+                show_help(args[0].as_str());
+                eprintln!("\nAt most one file should be given to parse");
+                std::process::exit(1);
+            }
+
+            let result = match subcommand {
+                Some("replay") => run_replay(&options),
+                Some("parse") => run_parse(&options),
+                Some("diff") => run_diff(&options),
+                _ => run_main_loop(&options),
+            };
+            if let Err(error) = result {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        }
+        Err(error) => {
+            show_help(args[0].as_str());
+            eprintln!("\nProgram arguments could not be parsed: {error}");
             std::process::exit(1);
         }
-    } else {
-        show_help(args[0].as_str());
-        eprintln!("\nProgram arguments could not be parsed");
-        std::process::exit(1);
     }
 }
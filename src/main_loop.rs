@@ -1,10 +1,23 @@
 use crate::error::{ErrorWithContext, Result, ResultExt};
+use crate::output::status::StatusLine;
 use crate::output::timestamp::Timestamp;
-use crate::output::{self, Printer};
+use crate::output::{self, Lookahead, Printer};
 use crate::token::{SerialTokenizer, Token};
 use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, ScopedJoinHandle};
+use std::time::Duration;
+
+/// Time between checks if all streams have ended, while waiting for marks
+const MARK_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time between redraws of the status line, to update the times
+const STATUS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time between checks if streams have failed, while waiting for streams to be added
+const STREAM_CHECK_INTERVAL: Duration = Duration::from_millis(100);
 
 // Represents one or two loops reading tokens from streams printing to others, e.g., from stdout
 // and stderr of an executed command to stdout and stderr of this process.
@@ -13,16 +26,47 @@ pub struct MainLoop<'a> {
     prefix_length: usize,
     // Common Timestamp for stream loops to get common start point and delta that is not per stream
     timestamp: Arc<Mutex<Timestamp>>,
-    loops: Vec<StreamLoop<'a>>,
+    // Common line waiting for the next line of any stream, when looking ahead
+    lookahead: Lookahead<'a>,
+    // Streams with their names, read once running when the names to align lines with are known
+    streams: Vec<(
+        &'a mut (dyn Read + Send),
+        &'a mut (dyn Write + Send),
+        String,
+    )>,
+    // Streams added while running, until the sender is dropped
+    added_streams: Option<Receiver<AddedStream>>,
+    // Marks received while running, with the output to write them to
+    marks: Option<(Receiver<String>, &'a mut (dyn Write + Send))>,
+    // Status line redrawn while running
+    status: Option<Arc<StatusLine>>,
+    // Output for the end line, printed when all streams have ended
+    end_output: &'a mut (dyn Write + Send),
+}
+
+/// A stream added while running, e.g., a connection to a socket
+pub struct AddedStream {
+    pub input: Box<dyn Read + Send>,
+    pub output: Box<dyn Write + Send>,
+    pub name: String,
 }
 
 impl<'a> MainLoop<'a> {
-    pub fn new(options: output::Options) -> Self {
+    pub fn new(
+        options: output::Options,
+        timestamp: Arc<Mutex<Timestamp>>,
+        end_output: &'a mut (dyn Write + Send),
+    ) -> Self {
         Self {
             options,
             prefix_length: 0,
-            timestamp: Arc::new(Mutex::new(Timestamp::new())),
-            loops: vec![],
+            timestamp,
+            lookahead: Lookahead::default(),
+            streams: vec![],
+            added_streams: None,
+            marks: None,
+            status: None,
+            end_output,
         }
     }
 
@@ -33,62 +77,198 @@ impl<'a> MainLoop<'a> {
         prefix: &str,
     ) {
         self.prefix_length = std::cmp::max(self.prefix_length, prefix.len());
-        let mut options = self.options.clone();
-        options.prefix = prefix.to_string();
-        self.loops.push(StreamLoop::new(
-            input,
-            output,
-            self.timestamp.clone(),
-            options,
-        ));
+        self.streams.push((input, output, prefix.to_string()));
+    }
+
+    /// Adds streams received while running. All streams have ended when the sender has been
+    /// dropped and the streams added have ended. Names of added streams are aligned with the
+    /// longest name so far, lines of streams started earlier are not aligned with longer names.
+    pub fn add_streams_while_running(&mut self, streams: Receiver<AddedStream>) {
+        self.added_streams = Some(streams);
+    }
+
+    /// Writes a mark line, formatted like the end line, for each text received while running,
+    /// e.g., `⏱ Mark 1`, or `⏱ Mark 2: TEXT` for a text that isn't empty. The output should be
+    /// line buffered with the same lock as the streams, to not write within their lines.
+    pub fn add_marks(&mut self, marks: Receiver<String>, output: &'a mut (dyn Write + Send)) {
+        self.marks = Some((marks, output));
+    }
+
+    /// Redraws a status line, if given, while running. It is cleared before the end line.
+    pub fn show_status(&mut self, status: Option<Arc<StatusLine>>) {
+        self.status = status;
     }
 
     // Loops and consumes the object
-    pub fn run(self) -> Result<()> {
+    pub fn run(mut self) -> Result<()> {
+        // Names of all streams given before running are aligned
+        self.options.stream_width = self.prefix_length;
+        let streams_ended = AtomicBool::new(false);
+        // Marks are aligned with streams added while running
+        let prefix_length = AtomicUsize::new(self.prefix_length);
         thread::scope(|s| {
-            let threads = self
-                .loops
+            let mark_thread = self.marks.take().map(|(marks, output)| {
+                let (options, timestamp) = (&self.options, &self.timestamp);
+                let (prefix_length, streams_ended) = (&prefix_length, &streams_ended);
+                s.spawn(move || {
+                    let mut count = 0;
+                    loop {
+                        let text = match marks.recv_timeout(MARK_CHECK_INTERVAL) {
+                            Ok(text) => text,
+                            Err(RecvTimeoutError::Timeout)
+                                if !streams_ended.load(Ordering::SeqCst) =>
+                            {
+                                continue
+                            }
+                            Err(_) => return Ok(()),
+                        };
+                        count += 1;
+                        let stamp = output::timestamp::take(timestamp, false);
+                        let length = prefix_length.load(Ordering::SeqCst);
+                        let prefix = mark_prefix(options, length, stamp);
+                        let separator = if text.is_empty() { "" } else { ": " };
+                        writeln!(output, "{prefix}\u{23f1} Mark {count}{separator}{text}")
+                            .and_then(|_| output.flush())
+                            .error_context("Error writing to stdout")?;
+                    }
+                })
+            });
+            let status_thread = self.status.clone().map(|status| {
+                let streams_ended = &streams_ended;
+                s.spawn(move || {
+                    while !streams_ended.load(Ordering::SeqCst) {
+                        status.redraw();
+                        thread::sleep(STATUS_INTERVAL);
+                    }
+                })
+            });
+            let mut threads = self
+                .streams
                 .into_iter()
-                .map(|mut l| s.spawn(move || l.loop_stream()))
+                .map(|(input, output, prefix)| {
+                    let mut options = self.options.clone();
+                    options.prefix = prefix;
+                    let printer = Printer::new(output, self.timestamp.clone(), options)
+                        .with_lookahead(self.lookahead.clone());
+                    s.spawn(move || StreamLoop::new(input, printer).loop_stream())
+                })
                 .collect::<Vec<ScopedJoinHandle<Result<()>>>>();
-            for t in threads {
+            // Streams that have failed, e.g., when output can't be written, end the loop without
+            // waiting for more streams to be added
+            let mut result = Ok(());
+            if let Some(added_streams) = self.added_streams {
+                while result.is_ok() {
+                    match added_streams.recv_timeout(STREAM_CHECK_INTERVAL) {
+                        Ok(stream) => {
+                            let length = stream.name.len();
+                            let longest = prefix_length.fetch_max(length, Ordering::SeqCst);
+                            let mut options = self.options.clone();
+                            options.prefix = stream.name;
+                            options.stream_width = longest.max(length);
+                            let printer =
+                                Printer::owning(stream.output, self.timestamp.clone(), options)
+                                    .with_lookahead(self.lookahead.clone());
+                            threads.push(s.spawn(move || {
+                                let mut input = stream.input;
+                                StreamLoop::new(input.as_mut(), printer).loop_stream()
+                            }));
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    result = join_finished(&mut threads);
+                }
+            }
+            let result = result.and_then(|_| {
+                threads.into_iter().try_for_each(|t| {
+                    t.join()
+                        .expect("Thread reading tokens unexpectedly panicked")
+                })
+            });
+            // Taken before waiting for the threads writing marks and drawing the status line to
+            // notice that the streams have ended, which they check at intervals
+            let end_stamp = output::timestamp::take(&self.timestamp, false);
+            // Marks are written and the status line drawn until the streams have ended
+            streams_ended.store(true, Ordering::SeqCst);
+            if let Some(t) = mark_thread {
                 t.join()
-                    .expect("Thread reading tokens unexpectedly panicked")?;
+                    .expect("Thread writing marks unexpectedly panicked")?;
+            }
+            if let Some(t) = status_thread {
+                t.join()
+                    .expect("Thread drawing status line unexpectedly panicked");
+            }
+            if let Some(status) = &self.status {
+                status.clear();
             }
+            result?;
 
-            let timestamp_prefix = output::timestamp::create_prefix(
-                &self.timestamp,
-                self.options.show_delta,
-                self.options.microseconds,
-            );
-            println!(
-                "{}{}{}: \u{23f1} End",
-                timestamp_prefix,
-                if self.prefix_length > 0 { " " } else { "" },
-                "-".repeat(self.prefix_length)
+            let end_prefix = mark_prefix(
+                &self.options,
+                prefix_length.load(Ordering::SeqCst),
+                end_stamp,
             );
+            let suppressed = match &self.options.filter {
+                Some(filter) => match filter.suppressed() {
+                    1 => " (1 line suppressed)".to_string(),
+                    count => format!(" ({count} lines suppressed)"),
+                },
+                None => String::new(),
+            };
+            writeln!(self.end_output, "{end_prefix}\u{23f1} End{suppressed}")
+                .and_then(|_| self.end_output.flush())
+                .error_context("Error writing to stdout")?;
+            self.options
+                .observers
+                .end(end_stamp.time.unwrap_or_default(), self.end_output)
+                .and_then(|_| self.end_output.flush())
+                .error_context("Error writing analysis of lines")?;
 
             Ok(())
         })
     }
 }
 
+/// Joins the threads of streams that have ended, to get errors while running
+fn join_finished(threads: &mut Vec<ScopedJoinHandle<Result<()>>>) -> Result<()> {
+    while let Some(index) = threads.iter().position(ScopedJoinHandle::is_finished) {
+        threads
+            .swap_remove(index)
+            .join()
+            .expect("Thread reading tokens unexpectedly panicked")?;
+    }
+    Ok(())
+}
+
+/// Renders the prefix of a line written by linetime, e.g., the end line, with dashes as stream
+fn mark_prefix(
+    options: &output::Options,
+    prefix_length: usize,
+    stamp: output::timestamp::Stamp,
+) -> String {
+    options
+        .prefix_template(prefix_length > 0)
+        .render(&output::template::Values {
+            stamp,
+            stream: "-".repeat(prefix_length).as_str(),
+            line: None,
+            stream_line: None,
+            next: None,
+            microseconds: options.microseconds,
+        })
+}
+
 // Represents a loop reading tokens from one stream and printing to another
-struct StreamLoop<'a> {
-    tokenizer: SerialTokenizer<'a>,
-    printer: Printer<'a>,
+struct StreamLoop<'r, 'w> {
+    tokenizer: SerialTokenizer<'r>,
+    printer: Printer<'w>,
 }
 
-impl<'a> StreamLoop<'a> {
-    fn new(
-        input_stream: &'a mut (dyn Read + Send),
-        output_stream: &'a mut (dyn Write + Send),
-        timestamp: Arc<Mutex<Timestamp>>,
-        output_options: output::Options,
-    ) -> Self {
+impl<'r, 'w> StreamLoop<'r, 'w> {
+    fn new(input_stream: &'r mut (dyn Read + Send), printer: Printer<'w>) -> Self {
         Self {
             tokenizer: SerialTokenizer::new(input_stream),
-            printer: Printer::new(output_stream, timestamp, output_options),
+            printer,
         }
     }
 
@@ -111,3 +291,103 @@ impl<'a> StreamLoop<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SharedVec;
+
+    /// Output failing like stdout closed by the reader, e.g., `head -1`
+    struct ClosedOutput;
+
+    impl Write for ClosedOutput {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failing_added_stream_ends_loop_while_more_streams_can_be_added() {
+        let mut end_output = SharedVec::default();
+        let mut timestamp = Timestamp::new();
+        timestamp.expect_get(Duration::from_millis(12));
+        timestamp.expect_get(Duration::from_millis(34));
+        let timestamp = Arc::new(Mutex::new(timestamp));
+        let mut main_loop = MainLoop::new(output::Options::default(), timestamp, &mut end_output);
+        let (streams, added_streams) = std::sync::mpsc::channel();
+        main_loop.add_streams_while_running(added_streams);
+        streams
+            .send(AddedStream {
+                input: Box::new(&b"line\n"[..]),
+                output: Box::new(ClosedOutput),
+                name: "tests".to_string(),
+            })
+            .unwrap();
+
+        assert!(main_loop.run().is_err());
+        drop(streams);
+    }
+
+    /// Reads or writes, and notifies when reading or flushing, to wait for the main loop
+    struct Notifying<T>(T, std::sync::mpsc::Sender<()>);
+
+    impl<T: Read> Read for Notifying<T> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let _ = self.1.send(());
+            self.0.read(buf)
+        }
+    }
+
+    impl<T: Write> Write for Notifying<T> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            let _ = self.1.send(());
+            self.0.flush()
+        }
+    }
+
+    #[test]
+    fn marks_are_aligned_with_streams_added_while_running() {
+        let mut end_output = SharedVec::default();
+        let mark_output = SharedVec::default();
+        let (notify, notified) = std::sync::mpsc::channel();
+        let mut notifying_mark_output = Notifying(mark_output.clone(), notify.clone());
+        let mut timestamp = Timestamp::new();
+        timestamp.expect_get(Duration::from_millis(12));
+        timestamp.expect_get(Duration::from_millis(34));
+        let timestamp = Arc::new(Mutex::new(timestamp));
+        let mut main_loop = MainLoop::new(output::Options::default(), timestamp, &mut end_output);
+        let (streams, added_streams) = std::sync::mpsc::channel();
+        let (marks, received_marks) = std::sync::mpsc::channel();
+        main_loop.add_streams_while_running(added_streams);
+        main_loop.add_marks(received_marks, &mut notifying_mark_output);
+        let written_marks = mark_output.clone();
+        let sender = std::thread::spawn(move || {
+            streams
+                .send(AddedStream {
+                    input: Box::new(Notifying(&b""[..], notify)),
+                    output: Box::new(SharedVec::default()),
+                    name: "tests".to_string(),
+                })
+                .unwrap();
+            // Marks once the stream is read, and ends the streams once the mark is written
+            notified.recv().unwrap();
+            marks.send(String::new()).unwrap();
+            while written_marks.text().is_empty() {
+                notified.recv().unwrap();
+            }
+        });
+
+        main_loop.run().unwrap();
+        sender.join().unwrap();
+        assert_eq!("00:00.012 -----: \u{23f1} Mark 1\n", mark_output.text());
+        assert_eq!("00:00.034 -----: \u{23f1} End\n", end_output.text());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::error::{ErrorWithContext, Result};
+use crate::listen::FifoReader;
+use std::io::BufRead;
+use std::sync::mpsc::Sender;
+
+/// Sends a mark without text each time SIGUSR1 is received
+pub fn on_signal(marks: Sender<String>) -> Result<()> {
+    #[cfg(unix)]
+    return crate::signal::on_signals(&[libc::SIGUSR1], false, move || {
+        marks.send(String::new()).is_ok()
+    });
+    #[cfg(not(unix))]
+    {
+        let _ = marks;
+        Ok(())
+    }
+}
+
+/// Sends a mark for each line written to a control FIFO, with the line as text. The FIFO is
+/// created if it doesn't exist.
+pub fn read_control(path: &str, marks: Sender<String>) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.file_type().is_fifo() => {}
+            Ok(_) => {
+                return Err(ErrorWithContext::wrap(
+                    format!("Failed to open control FIFO '{path}'"),
+                    std::io::Error::other("The file exists and is not a FIFO"),
+                ))
+            }
+            Err(_) => {
+                let c_path = std::ffi::CString::new(path).map_err(|error| {
+                    ErrorWithContext::wrap(format!("Failed to create control FIFO '{path}'"), error)
+                })?;
+                // SAFETY: mkfifo() only reads the null terminated path
+                if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                    return Err(ErrorWithContext::wrap(
+                        format!("Failed to create control FIFO '{path}'"),
+                        std::io::Error::last_os_error(),
+                    ));
+                }
+            }
+        }
+        let control = std::io::BufReader::new(FifoReader::new(path));
+        std::thread::spawn(move || {
+            for line in control.lines() {
+                let Ok(line) = line else {
+                    return;
+                };
+                if marks.send(line.trim().to_string()).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = marks;
+        Err(ErrorWithContext::wrap(
+            format!("Failed to open control FIFO '{path}'"),
+            std::io::Error::other("Control FIFOs are only supported on Unix"),
+        ))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::io::Write;
+    use std::time::Duration;
+
+    #[test]
+    fn lines_written_to_control_fifo_are_sent() {
+        let dir = TempDir::new("control");
+        let path = dir.file("control");
+        let (sender, marks) = std::sync::mpsc::channel();
+        read_control(&path, sender).unwrap();
+
+        for text in ["clicked\n", "\n"] {
+            let mut fifo = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+            fifo.write_all(text.as_bytes()).unwrap();
+        }
+        let timeout = Duration::from_secs(5);
+        assert_eq!(Ok("clicked".to_string()), marks.recv_timeout(timeout));
+        assert_eq!(Ok(String::new()), marks.recv_timeout(timeout));
+    }
+}
@@ -1,22 +1,35 @@
+use crate::analysis::{self, Observers};
 use crate::token::escape;
 use crate::token::Token;
 use std::collections::VecDeque;
 use std::io::Write;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
 pub mod buffered;
+pub mod filter;
+pub mod status;
+pub mod template;
 pub mod timestamp;
 
+use self::filter::Filter;
+use self::template::{LineNumber, Template};
 use self::timestamp::Timestamp;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Options {
     /// Show delta time since previous line
     pub show_delta: bool,
     /// Microseconds in timestamps and delta times
     pub microseconds: bool,
+    /// Show line number common for all streams
+    pub line_numbers: bool,
+    /// Show line number for each stream
+    pub stream_line_numbers: bool,
     /// Prefix added to start of each line together with a timestamp
     pub prefix: String,
+    /// Width the prefix is padded to in the default layout, to align lines of all streams
+    pub stream_width: usize,
     /// Show control characters as unicode symbols
     pub show_control: bool,
     /// Show handled escape sequences as string with unciode symbol for the escape character
@@ -25,15 +38,197 @@ pub struct Options {
     pub dump_tokens: bool,
     /// Flush output stream after each token
     pub flush_all: bool,
+    /// Template for the line prefix, the default layout is used if not set
+    pub template: Option<Template>,
+    /// Observers getting each printed line
+    pub observers: Observers,
+    /// Hold each line until the next line of any stream, to show the time until the next line
+    pub lookahead: bool,
+    /// Filter for complete lines, all lines are shown if not set
+    pub filter: Option<Filter>,
+}
+
+impl Options {
+    /// Gets the template for line prefixes, either the custom template or the default layout
+    pub fn prefix_template(&self, show_stream: bool) -> Template {
+        self.template
+            .clone()
+            .unwrap_or_else(|| Template::default_layout(self, show_stream))
+    }
 }
 
 pub struct Printer<'a> {
-    stream: &'a mut (dyn Write + Send),
+    stream: SharedOutput<'a>,
     options: Options,
+    template: Arc<Template>,
 
     timestamp: Arc<Mutex<Timestamp>>,
     start_of_line: bool,
     break_tokens: VecDeque<Token>,
+
+    // Line numbers of the current line, common for all streams and for this stream only
+    line_number: LineNumber,
+    stream_line_number: LineNumber,
+    // Set when the current line was broken by a soft break, making the next line a part of the
+    // same line
+    continued_line: bool,
+    // Time and printable text of the current line, for observers
+    line_time: Option<Duration>,
+    line_text: String,
+    // Line held until it is complete, when looking ahead or to filter it
+    held: Option<HeldLine>,
+    // Lines held until the time to the next line of any stream is known, when looking ahead
+    lookahead: Lookahead<'a>,
+    // Lines not matching the filter, kept to show as context before a matching line
+    context_before: VecDeque<HeldLine>,
+    // Number of lines left to show as context after a matching line
+    context_after: usize,
+    // Set when lines were suppressed since the last shown line, and if any line was shown, to
+    // separate context lines that are not adjacent
+    skipped: bool,
+    shown_any: bool,
+}
+
+/// A line with its prefix not yet rendered, and the output following the prefix
+struct HeldLine {
+    stamp: timestamp::Stamp,
+    line_number: LineNumber,
+    stream_line_number: LineNumber,
+    output: Vec<u8>,
+    /// Printable text of the complete line, when filtering
+    text: String,
+    /// Start of the next line of any stream, set when it starts, when looking ahead
+    next_start: Arc<OnceLock<Duration>>,
+}
+
+/// Output of a printer, borrowed, or owned for a stream added while running
+enum Output<'a> {
+    Borrowed(&'a mut (dyn Write + Send)),
+    Owned(Box<dyn Write + Send>),
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Borrowed(stream) => stream.write(buf),
+            Self::Owned(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Borrowed(stream) => stream.flush(),
+            Self::Owned(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Output shared by a printer and the lines it holds, to let any printer write a held line
+type SharedOutput<'a> = Arc<Mutex<Output<'a>>>;
+
+fn write_shared(output: &SharedOutput, buf: &[u8]) -> Result<(), std::io::Error> {
+    // If another thread panicked while writing, we proceed anyway
+    let mut output = output.lock().unwrap_or_else(|error| error.into_inner());
+    output.write_all(buf)
+}
+
+/// A shown line with what its prefix is rendered with, written when the time until the next line
+/// is known, when looking ahead
+struct ShownLine<'a> {
+    held: HeldLine,
+    template: Arc<Template>,
+    stream: String,
+    microseconds: bool,
+    output: SharedOutput<'a>,
+}
+
+impl ShownLine<'_> {
+    fn write(self) -> Result<(), std::io::Error> {
+        let next = self
+            .held
+            .next_start
+            .get()
+            .zip(self.held.stamp.time)
+            .map(|(next_start, time)| next_start.saturating_sub(time));
+        let prefix = self.template.render(&template::Values {
+            stamp: self.held.stamp,
+            stream: &self.stream,
+            line: Some(self.held.line_number),
+            stream_line: Some(self.held.stream_line_number),
+            next,
+            microseconds: self.microseconds,
+        });
+        // The line is written at once to keep lines whole when writing to a line buffered output
+        let mut line = prefix.into_bytes();
+        line.extend(self.held.output);
+        write_shared(&self.output, &line)
+    }
+}
+
+/// The line waiting for the next line of any stream to start, when looking ahead, shared by the
+/// printers of all streams. Lines are written with the lock held, to keep them in order.
+#[derive(Clone, Default)]
+pub struct Lookahead<'a>(Arc<Mutex<LookaheadState<'a>>>);
+
+#[derive(Default)]
+struct LookaheadState<'a> {
+    // Start of the line after the latest line started, set when it starts
+    next_start: Arc<OnceLock<Duration>>,
+    // The latest line started, if shown before the next line started
+    waiting: Option<ShownLine<'a>>,
+}
+
+impl<'a> Lookahead<'a> {
+    /// Registers the start of a line, and writes the waiting line now that the time until the
+    /// next line is known. Gets where the start of the line after this line will be set.
+    fn start(&self, time: Option<Duration>) -> Result<Arc<OnceLock<Duration>>, std::io::Error> {
+        let mut state = self.lock();
+        if let Some(time) = time {
+            let _ = state.next_start.set(time);
+        }
+        state.next_start = Arc::default();
+        if let Some(line) = state.waiting.take() {
+            line.write()?;
+        }
+        Ok(state.next_start.clone())
+    }
+
+    /// Writes a shown line if the next line has started, or else keeps it waiting until then
+    fn show(&self, line: ShownLine<'a>) -> Result<(), std::io::Error> {
+        let mut state = self.lock();
+        if line.held.next_start.get().is_some() {
+            return line.write();
+        }
+        match state.waiting.replace(line) {
+            Some(earlier) => earlier.write(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the waiting line, if written to an output, with the time until the end of its
+    /// stream
+    fn end(&self, output: &SharedOutput<'a>, time: Option<Duration>) -> Result<(), std::io::Error> {
+        let mut state = self.lock();
+        if !state
+            .waiting
+            .as_ref()
+            .is_some_and(|line| Arc::ptr_eq(&line.output, output))
+        {
+            return Ok(());
+        }
+        if let Some(time) = time {
+            let _ = state.next_start.set(time);
+        }
+        match state.waiting.take() {
+            Some(line) => line.write(),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LookaheadState<'a>> {
+        // If another thread panicked while writing, we proceed anyway
+        self.0.lock().unwrap_or_else(|error| error.into_inner())
+    }
 }
 
 impl<'a> Printer<'a> {
@@ -42,15 +237,47 @@ impl<'a> Printer<'a> {
         timestamp: Arc<Mutex<Timestamp>>,
         options: Options,
     ) -> Self {
+        Self::with_output(Output::Borrowed(stream), timestamp, options)
+    }
+
+    /// Creates a printer owning the stream it prints to, e.g., for a stream added while running
+    pub fn owning(
+        stream: Box<dyn Write + Send>,
+        timestamp: Arc<Mutex<Timestamp>>,
+        options: Options,
+    ) -> Self {
+        Self::with_output(Output::Owned(stream), timestamp, options)
+    }
+
+    fn with_output(output: Output<'a>, timestamp: Arc<Mutex<Timestamp>>, options: Options) -> Self {
         Self {
-            stream,
+            stream: Arc::new(Mutex::new(output)),
+            template: Arc::new(options.prefix_template(!options.prefix.is_empty())),
             options,
             timestamp,
             start_of_line: true,
             break_tokens: VecDeque::new(),
+            line_number: LineNumber::default(),
+            stream_line_number: LineNumber::default(),
+            continued_line: false,
+            line_time: None,
+            line_text: String::new(),
+            held: None,
+            lookahead: Lookahead::default(),
+            context_before: VecDeque::new(),
+            context_after: 0,
+            skipped: false,
+            shown_any: false,
         }
     }
 
+    /// Shares the line waiting for the next line with the printers of other streams, to show the
+    /// time until the next line of any stream when looking ahead
+    pub fn with_lookahead(mut self, lookahead: Lookahead<'a>) -> Self {
+        self.lookahead = lookahead;
+        self
+    }
+
     pub fn print(&mut self, token: &Token) -> Result<(), std::io::Error> {
         if self.options.dump_tokens {
             eprintln!("{token:?}");
@@ -62,6 +289,7 @@ impl<'a> Printer<'a> {
             // Soft break triggers newline when not followed by a linefeed, to unwrap lines
             // otherwise being overwritten in the terminal
             self.newline()?;
+            self.continued_line = true;
         }
 
         if self.start_of_line && *token != Token::EndOfFile {
@@ -75,8 +303,20 @@ impl<'a> Printer<'a> {
             // Ensure ending on a new line after EOF
             self.newline()?;
         }
+        if *token == Token::EndOfFile {
+            if self.options.lookahead {
+                // The last line shown waits for the next line of any stream, or the end of this
+                let now = self.timestamp.lock().map(|timestamp| timestamp.now()).ok();
+                self.lookahead.end(&self.stream, now)?;
+            }
+            self.end_context();
+        }
         if self.options.flush_all {
-            self.stream.flush()?;
+            let mut stream = self
+                .stream
+                .lock()
+                .unwrap_or_else(|error| error.into_inner());
+            stream.flush()?;
         }
 
         Ok(())
@@ -96,7 +336,12 @@ impl<'a> Printer<'a> {
     }
 
     fn print_str(&mut self, s: &str) -> Result<(), std::io::Error> {
-        self.stream.write_all(s.as_ref())
+        if let Some(held) = &mut self.held {
+            held.output.extend_from_slice(s.as_bytes());
+            Ok(())
+        } else {
+            write_shared(&self.stream, s.as_bytes())
+        }
     }
 
     fn print_control(&mut self, s: &str) -> Result<(), std::io::Error> {
@@ -116,6 +361,7 @@ impl<'a> Printer<'a> {
     fn print_token(&mut self, token: &Token) -> Result<(), std::io::Error> {
         match token {
             Token::Char(c) => {
+                self.line_text.push(*c);
                 let mut buffer: [u8; 4] = [0; 4];
                 self.print_str(c.encode_utf8(&mut buffer))
             }
@@ -141,24 +387,169 @@ impl<'a> Printer<'a> {
         }
 
         self.print_str("\n")?;
+        if self.options.filter.is_some() {
+            if let Some(held) = &mut self.held {
+                held.text.clone_from(&self.line_text);
+            }
+        }
+        if let Some(time) = self.line_time.take() {
+            let banners = self.options.observers.line(&analysis::Line {
+                time,
+                stream: &self.options.prefix,
+                text: &self.line_text,
+            });
+            for banner in banners {
+                self.banner(time, banner.as_str())?;
+            }
+        }
+        // Banners are held with the line, to be shown only when the line is shown, after it
+        self.release_held_line()?;
+        self.line_text.clear();
         self.start_of_line = true;
+        self.continued_line = false;
         Ok(())
     }
 
+    /// Prints a banner line, with the same timestamp as the line that caused it
+    fn banner(&mut self, time: Duration, banner: &str) -> Result<(), std::io::Error> {
+        let prefix = self.template.render(&template::Values {
+            stamp: timestamp::Stamp {
+                time: Some(time),
+                ..Default::default()
+            },
+            stream: "-"
+                .repeat(self.options.prefix.len().max(self.options.stream_width))
+                .as_str(),
+            line: None,
+            stream_line: None,
+            next: None,
+            microseconds: self.options.microseconds,
+        });
+        self.print_str(format!("{prefix}{banner}\n").as_str())
+    }
+
     fn line_prefix(&mut self) -> Result<(), std::io::Error> {
-        let timestamp_prefix = timestamp::create_prefix(
-            &self.timestamp,
-            self.options.show_delta,
-            self.options.microseconds,
-        );
-        self.print_str(timestamp_prefix.as_str())?;
-        if !self.options.prefix.is_empty() {
-            self.print_str(format!(" {}", self.options.prefix).as_str())?;
+        let stamp = timestamp::take(&self.timestamp, !self.continued_line);
+        self.line_time = stamp.time;
+        if self.continued_line {
+            self.line_number.part += 1;
+            self.stream_line_number.part += 1;
+        } else {
+            self.line_number = LineNumber {
+                line: stamp.line.unwrap_or_default(),
+                part: 0,
+            };
+            self.stream_line_number = LineNumber {
+                line: self.stream_line_number.line + 1,
+                part: 0,
+            };
+        }
+
+        if self.options.lookahead || self.options.filter.is_some() {
+            let next_start = if self.options.lookahead {
+                // A line waiting for this line is written now that the time to this line is known
+                self.lookahead.start(stamp.time)?
+            } else {
+                Arc::default()
+            };
+            self.held = Some(HeldLine {
+                stamp,
+                line_number: self.line_number,
+                stream_line_number: self.stream_line_number,
+                output: vec![],
+                text: String::new(),
+                next_start,
+            });
+        } else {
+            let prefix = self.template.render(&template::Values {
+                stamp,
+                stream: &self.options.prefix,
+                line: Some(self.line_number),
+                stream_line: Some(self.stream_line_number),
+                next: None,
+                microseconds: self.options.microseconds,
+            });
+            self.print_str(prefix.as_str())?;
         }
-        self.print_str(": ")?;
         self.start_of_line = false;
         Ok(())
     }
+
+    /// Releases the held line, if any, when complete. When filtering, the line is shown if it
+    /// matches or is context after a matching line, or kept as context before a later matching
+    /// line.
+    fn release_held_line(&mut self) -> Result<(), std::io::Error> {
+        let Some(mut held) = self.held.take() else {
+            return Ok(());
+        };
+        let Some(filter) = &self.options.filter else {
+            return self.write_line(held);
+        };
+
+        let context = filter.context();
+        if context == 0 {
+            if filter.check(&mut held.stamp, &held.text) {
+                return self.write_line(held);
+            }
+            return Ok(());
+        }
+        if filter.matches(&held.text) {
+            self.context_after = context;
+            for line in std::mem::take(&mut self.context_before) {
+                self.show_line(line)?;
+            }
+        } else if self.context_after > 0 {
+            self.context_after -= 1;
+        } else {
+            self.context_before.push_back(held);
+            if self.context_before.len() > context {
+                self.context_before.pop_front();
+                filter.suppress();
+                self.skipped = true;
+            }
+            return Ok(());
+        }
+        self.show_line(held)
+    }
+
+    /// Shows a line when showing context, after a separator if lines before it were skipped
+    fn show_line(&mut self, mut held: HeldLine) -> Result<(), std::io::Error> {
+        if let Some(filter) = &self.options.filter {
+            if self.skipped && self.shown_any {
+                write_shared(&self.stream, b"--\n")?;
+            }
+            filter.show(&mut held.stamp);
+        }
+        self.skipped = false;
+        self.shown_any = true;
+        self.write_line(held)
+    }
+
+    /// Writes a shown line. When looking ahead, the line waits for the next line of any stream if
+    /// not started yet.
+    fn write_line(&self, held: HeldLine) -> Result<(), std::io::Error> {
+        let line = ShownLine {
+            held,
+            template: self.template.clone(),
+            stream: self.options.prefix.clone(),
+            microseconds: self.options.microseconds,
+            output: self.stream.clone(),
+        };
+        if self.options.lookahead {
+            self.lookahead.show(line)
+        } else {
+            line.write()
+        }
+    }
+
+    /// Suppresses the lines kept as context when there are no more lines
+    fn end_context(&mut self) {
+        if let Some(filter) = &self.options.filter {
+            for _ in self.context_before.drain(..) {
+                filter.suppress();
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -202,6 +593,7 @@ mod tests {
                 show_escape: true,
                 dump_tokens: false,
                 flush_all: false,
+                ..Default::default()
             },
         )
     }
@@ -418,6 +810,7 @@ mod tests {
                 show_escape: true,
                 dump_tokens: false,
                 flush_all: false,
+                ..Default::default()
             },
         );
 
@@ -443,6 +836,7 @@ mod tests {
                 show_escape: false,
                 dump_tokens: false,
                 flush_all: false,
+                ..Default::default()
             },
         );
 
@@ -473,6 +867,7 @@ mod tests {
                 show_escape: false,
                 dump_tokens: false,
                 flush_all: false,
+                ..Default::default()
             },
         );
 
@@ -497,6 +892,7 @@ mod tests {
                 show_escape: false,
                 dump_tokens: false,
                 flush_all: false,
+                ..Default::default()
             },
         );
 
@@ -528,6 +924,7 @@ mod tests {
                 show_escape: false,
                 dump_tokens: false,
                 flush_all: false,
+                ..Default::default()
             },
         );
 
@@ -544,4 +941,310 @@ mod tests {
             "00:03.100456 (00:00.100333): B"
         );
     }
+
+    #[test]
+    fn prefix_template_replaces_default_layout() {
+        let mut stream = Vec::<u8>::new();
+        let mut printer = Printer::new(
+            &mut stream,
+            Arc::new(Mutex::new(Timestamp::new())),
+            Options {
+                prefix: "stderr".to_string(),
+                template: Some("{elapsed}\\t{delta}\\t[{stream:>8}]\\t".parse().unwrap()),
+                ..Default::default()
+            },
+        );
+
+        expect_get_timestamp(&mut printer, Duration::from_millis(3000));
+        expect_get_timestamp(&mut printer, Duration::from_millis(3100));
+        printer.print(&Token::Char('A')).unwrap();
+        printer.print(&Token::LineFeed).unwrap();
+        printer.print(&Token::Char('B')).unwrap();
+
+        assert_all_timestamps_used(&printer);
+        assert_printed!(
+            stream,
+            "00:03.000\t           \t[  stderr]\tA\n",
+            "00:03.100\t(00:00.100)\t[  stderr]\tB"
+        );
+    }
+
+    #[test]
+    fn line_numbers_are_counted_and_unfolded_lines_get_part_numbers() {
+        let mut stream = Vec::<u8>::new();
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        // Another stream sharing the common line count
+        timestamp.lock().unwrap().count_line();
+        let mut printer = Printer::new(
+            &mut stream,
+            timestamp,
+            Options {
+                prefix: "stdout".to_string(),
+                line_numbers: true,
+                stream_line_numbers: true,
+                ..Default::default()
+            },
+        );
+
+        expect_get_timestamp(&mut printer, Duration::from_secs(3));
+        expect_get_timestamp(&mut printer, Duration::from_secs(4));
+        expect_get_timestamp(&mut printer, Duration::from_secs(5));
+        expect_get_timestamp(&mut printer, Duration::from_secs(6));
+        printer.print(&Token::Char('A')).unwrap();
+        printer.print(&Token::LineFeed).unwrap();
+        printer.print(&Token::Char('B')).unwrap();
+        printer.print(&Token::CarriageReturn).unwrap();
+        printer.print(&Token::Char('C')).unwrap();
+        printer.print(&Token::CarriageReturn).unwrap();
+        printer.print(&Token::Char('D')).unwrap();
+        printer.print(&Token::CarriageReturn).unwrap();
+        printer.print(&Token::LineFeed).unwrap();
+
+        assert_all_timestamps_used(&printer);
+        assert_printed!(
+            stream,
+            "00:03.000 2 stdout:1: A\n",
+            "00:04.000 3 stdout:2: B\r\n",
+            "00:05.000 3.1 stdout:2.1: C\r\n",
+            "00:06.000 3.2 stdout:2.2: D\r\n"
+        );
+    }
+
+    /// Observer collecting the lines it gets
+    #[derive(Clone, Default)]
+    struct LineCollector(Arc<Mutex<Vec<(Duration, String, String)>>>);
+
+    impl analysis::Observer for LineCollector {
+        fn line(&mut self, line: &analysis::Line) -> Option<String> {
+            self.0.lock().unwrap().push((
+                line.time,
+                line.stream.to_string(),
+                line.text.to_string(),
+            ));
+            (line.text == "B").then(|| "banner".to_string())
+        }
+
+        fn end(&mut self, _time: Duration, _output: &mut dyn Write) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn observers_get_printable_text_of_unfolded_lines_and_add_banners() {
+        let mut stream = Vec::<u8>::new();
+        let collector = LineCollector::default();
+        let options = Options {
+            prefix: "stdout".to_string(),
+            ..Default::default()
+        };
+        options.observers.add(collector.clone());
+        let mut printer =
+            Printer::new(&mut stream, Arc::new(Mutex::new(Timestamp::new())), options);
+
+        expect_get_timestamp(&mut printer, Duration::from_secs(3));
+        expect_get_timestamp(&mut printer, Duration::from_secs(4));
+        printer
+            .print(&esc_token!(escape::SequenceCommand::Unhandled, "\x1b[31m"))
+            .unwrap();
+        printer.print(&Token::Char('A')).unwrap();
+        printer.print(&Token::CarriageReturn).unwrap();
+        printer.print(&Token::Char('B')).unwrap();
+        printer.print(&Token::EndOfFile).unwrap();
+
+        assert_all_timestamps_used(&printer);
+        assert_eq!(
+            vec![
+                (
+                    Duration::from_secs(3),
+                    "stdout".to_string(),
+                    "A".to_string()
+                ),
+                (
+                    Duration::from_secs(4),
+                    "stdout".to_string(),
+                    "B".to_string()
+                )
+            ],
+            *collector.0.lock().unwrap()
+        );
+        assert_printed!(
+            stream,
+            "00:03.000 stdout: \x1b[31mA\r\n",
+            "00:04.000 stdout: B\n",
+            "00:04.000 ------: banner\n"
+        );
+    }
+
+    #[test]
+    fn banners_are_shown_with_their_lines_when_filtering() {
+        let include = regex::Regex::new("x").unwrap();
+        for lookahead in [false, true] {
+            let mut stream = Vec::<u8>::new();
+            let filter = Filter::new(vec![include.clone()], vec![], 1, false);
+            let options = Options {
+                filter: Some(filter),
+                lookahead,
+                ..Default::default()
+            };
+            options.observers.add(LineCollector::default());
+            let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+            let mut printer = Printer::new(&mut stream, timestamp.clone(), options);
+
+            // The banner of the suppressed line is suppressed, and the banner of the context
+            // line is shown after it
+            for (second, c) in "BaBxc".chars().enumerate() {
+                expect_get_timestamp(&mut printer, Duration::from_secs(second as u64));
+                printer.print(&Token::Char(c)).unwrap();
+                printer.print(&Token::LineFeed).unwrap();
+            }
+            timestamp.lock().unwrap().set(Duration::from_secs(5));
+            printer.print(&Token::EndOfFile).unwrap();
+
+            assert_all_timestamps_used(&printer);
+            let text = String::from_utf8(stream).unwrap();
+            let lines = text
+                .lines()
+                .map(|line| line.split_once(": ").unwrap().1)
+                .collect::<Vec<_>>();
+            assert_eq!(vec!["B", "banner", "x", "c"], lines, "{text}");
+        }
+    }
+
+    #[test]
+    fn lines_are_held_until_time_to_next_line_is_known_when_looking_ahead() {
+        let mut stream = Vec::<u8>::new();
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        let mut printer = Printer::new(
+            &mut stream,
+            timestamp.clone(),
+            Options {
+                lookahead: true,
+                ..Default::default()
+            },
+        );
+
+        expect_get_timestamp(&mut printer, Duration::from_secs(3));
+        expect_get_timestamp(&mut printer, Duration::from_secs(5));
+        printer.print(&Token::Char('A')).unwrap();
+        printer.print(&Token::LineFeed).unwrap();
+        printer.print(&Token::Char('B')).unwrap();
+        printer.print(&Token::CarriageReturn).unwrap();
+        // The last line is held until the end of input
+        assert!(printer.held.is_some());
+
+        timestamp.lock().unwrap().set(Duration::from_millis(5500));
+        printer.print(&Token::EndOfFile).unwrap();
+        assert_all_timestamps_used(&printer);
+        assert_printed!(
+            stream,
+            "00:03.000 [00:02.000]: A\n",
+            "00:05.000 [00:00.500]: B\r\n"
+        );
+    }
+
+    #[test]
+    fn time_until_next_line_of_any_stream_is_shown_when_looking_ahead() {
+        let (mut out, mut err) = (Vec::<u8>::new(), Vec::<u8>::new());
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        let lookahead = Lookahead::default();
+        let options = |prefix: &str| Options {
+            prefix: prefix.to_string(),
+            lookahead: true,
+            ..Default::default()
+        };
+        let mut out_printer = Printer::new(&mut out, timestamp.clone(), options("out"))
+            .with_lookahead(lookahead.clone());
+        let mut err_printer =
+            Printer::new(&mut err, timestamp.clone(), options("err")).with_lookahead(lookahead);
+
+        expect_get_timestamp(&mut out_printer, Duration::from_secs(1));
+        expect_get_timestamp(&mut out_printer, Duration::from_secs(2));
+        expect_get_timestamp(&mut out_printer, Duration::from_secs(4));
+        out_printer.print(&Token::Char('a')).unwrap();
+        err_printer.print(&Token::Char('b')).unwrap();
+        // The line is complete after the next line started, and is written at once
+        out_printer.print(&Token::LineFeed).unwrap();
+        err_printer.print(&Token::LineFeed).unwrap();
+        // The line of the other stream is written when the next line starts
+        out_printer.print(&Token::Char('c')).unwrap();
+        out_printer.print(&Token::LineFeed).unwrap();
+        err_printer.print(&Token::EndOfFile).unwrap();
+
+        timestamp.lock().unwrap().set(Duration::from_millis(4500));
+        out_printer.print(&Token::EndOfFile).unwrap();
+        assert_all_timestamps_used(&out_printer);
+        assert_printed!(
+            out,
+            "00:01.000 [00:01.000] out: a\n",
+            "00:04.000 [00:00.500] out: c\n"
+        );
+        assert_printed!(err, "00:02.000 [00:02.000] err: b\n");
+    }
+
+    #[test]
+    fn filtered_lines_are_suppressed_when_complete() {
+        let mut stream = Vec::<u8>::new();
+        let filter = Filter::new(vec![regex::Regex::new("^A").unwrap()], vec![], 0, false);
+        let mut printer = Printer::new(
+            &mut stream,
+            Arc::new(Mutex::new(Timestamp::new())),
+            Options {
+                show_delta: true,
+                filter: Some(filter.clone()),
+                ..Default::default()
+            },
+        );
+
+        expect_get_timestamp(&mut printer, Duration::from_secs(1));
+        expect_get_timestamp(&mut printer, Duration::from_secs(3));
+        expect_get_timestamp(&mut printer, Duration::from_secs(4));
+        printer.print(&Token::Char('B')).unwrap();
+        printer.print(&Token::LineFeed).unwrap();
+        printer.print(&Token::Char('A')).unwrap();
+        // An unfolded part is a line on its own
+        printer.print(&Token::CarriageReturn).unwrap();
+        printer.print(&Token::Char('z')).unwrap();
+        printer.print(&Token::EndOfFile).unwrap();
+
+        assert_all_timestamps_used(&printer);
+        assert_eq!(2, filter.suppressed());
+        assert_printed!(stream, "00:03.000 (00:02.000): A\r\n");
+    }
+
+    #[test]
+    fn context_lines_are_shown_around_matching_lines_with_separators() {
+        let mut stream = Vec::<u8>::new();
+        let filter = Filter::new(vec![regex::Regex::new("x").unwrap()], vec![], 1, false);
+        let mut printer = Printer::new(
+            &mut stream,
+            Arc::new(Mutex::new(Timestamp::new())),
+            Options {
+                filter: Some(filter.clone()),
+                ..Default::default()
+            },
+        );
+
+        for (second, c) in "abxcdexfghx".chars().enumerate() {
+            expect_get_timestamp(&mut printer, Duration::from_secs(second as u64));
+            printer.print(&Token::Char(c)).unwrap();
+            printer.print(&Token::LineFeed).unwrap();
+        }
+        printer.print(&Token::EndOfFile).unwrap();
+
+        assert_all_timestamps_used(&printer);
+        assert_eq!(3, filter.suppressed());
+        assert_printed!(
+            stream,
+            "00:01.000: b\n",
+            "00:02.000: x\n",
+            "00:03.000: c\n",
+            "--\n",
+            "00:05.000: e\n",
+            "00:06.000: x\n",
+            "00:07.000: f\n",
+            "--\n",
+            "00:09.000: h\n",
+            "00:10.000: x\n"
+        );
+    }
 }
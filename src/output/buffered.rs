@@ -1,24 +1,34 @@
+use super::status::StatusLine;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 
 /// A Write decorator that buffers lines and then writes the output to the inner Write
-pub struct LineWriteDecorator<'a> {
-    inner: &'a mut (dyn Write + Send),
+pub struct LineWriteDecorator<W> {
+    inner: W,
     buffer: Vec<u8>,
     write_mutex: Arc<Mutex<()>>,
+    // Status line erased before writing lines, and drawn again after
+    status: Option<Arc<StatusLine>>,
 }
 
-impl<'a> LineWriteDecorator<'a> {
-    pub fn new(inner: &'a mut (dyn Write + Send), write_mutex: Arc<Mutex<()>>) -> Self {
+impl<W: Write> LineWriteDecorator<W> {
+    pub fn new(inner: W, write_mutex: Arc<Mutex<()>>) -> Self {
         Self {
             inner,
             buffer: Vec::with_capacity(256),
             write_mutex,
+            status: None,
         }
     }
+
+    /// Sets a status line to erase while writing lines, drawn with the same lock
+    pub fn with_status(mut self, status: Option<Arc<StatusLine>>) -> Self {
+        self.status = status;
+        self
+    }
 }
 
-impl Write for LineWriteDecorator<'_> {
+impl<W: Write> Write for LineWriteDecorator<W> {
     fn write(&mut self, input: &[u8]) -> std::io::Result<usize> {
         for &i in input {
             self.buffer.push(i);
@@ -33,9 +43,17 @@ impl Write for LineWriteDecorator<'_> {
         // Lock mutex to ensure not writing lines to stdout and stderr at the same time. If the
         // another thread panicked we proceed anyway.
         let _lock = self.write_mutex.lock().ok();
+        let status = self.status.as_ref().filter(|_| !self.buffer.is_empty());
+        if let Some(status) = status {
+            status.erase();
+        }
         self.inner.write_all(self.buffer.as_slice())?;
         self.buffer.clear();
-        self.inner.flush()
+        self.inner.flush()?;
+        if let Some(status) = status {
+            status.draw();
+        }
+        Ok(())
     }
 }
 
@@ -0,0 +1,166 @@
+use super::timestamp::Stamp;
+use regex::Regex;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Filters complete lines by regexes. A line matches if it matches any include regex, or there
+/// are none, and no exclude regex. Lines of the same stream around a matching line can be shown
+/// as context. The filter is shared by the printers of all streams.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    /// Number of lines to show before and after a matching line
+    context: usize,
+    /// Compute delta times over shown lines only, instead of over all lines
+    shown_delta: bool,
+    state: Arc<Mutex<FilterState>>,
+}
+
+#[derive(Debug, Default)]
+struct FilterState {
+    previous_shown: Option<Duration>,
+    suppressed: u64,
+}
+
+impl Filter {
+    pub fn new(
+        include: Vec<Regex>,
+        exclude: Vec<Regex>,
+        context: usize,
+        shown_delta: bool,
+    ) -> Self {
+        Self {
+            include,
+            exclude,
+            context,
+            shown_delta,
+            state: Default::default(),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(text)))
+            && !self.exclude.iter().any(|regex| regex.is_match(text))
+    }
+
+    pub fn context(&self) -> usize {
+        self.context
+    }
+
+    /// Checks if a line is shown when not showing context, counting it if suppressed. The delta
+    /// time of a shown line is changed to the time since the previous shown line, when computing
+    /// deltas over shown lines.
+    pub fn check(&self, stamp: &mut Stamp, text: &str) -> bool {
+        let shown = self.matches(text);
+        if shown {
+            self.show(stamp);
+        } else {
+            self.suppress();
+        }
+        shown
+    }
+
+    /// Registers a line as shown. Its delta time is changed to the time since the previous shown
+    /// line, when computing deltas over shown lines.
+    pub fn show(&self, stamp: &mut Stamp) {
+        let Some(time) = stamp.time else {
+            return;
+        };
+        let mut state = self.lock();
+        if self.shown_delta {
+            stamp.delta = state
+                .previous_shown
+                .map(|previous| time.saturating_sub(previous));
+        }
+        state.previous_shown = Some(state.previous_shown.map_or(time, |t| t.max(time)));
+    }
+
+    /// Counts a suppressed line
+    pub fn suppress(&self) {
+        self.lock().suppressed += 1;
+    }
+
+    /// Gets the number of suppressed lines
+    pub fn suppressed(&self) -> u64 {
+        self.lock().suppressed
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FilterState> {
+        // If another thread panicked while filtering, we proceed anyway
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(millis: u64, delta: Option<u64>) -> Stamp {
+        Stamp {
+            time: Some(Duration::from_millis(millis)),
+            delta: delta.map(Duration::from_millis),
+            line: None,
+        }
+    }
+
+    fn regexes(regexes: &[&str]) -> Vec<Regex> {
+        regexes
+            .iter()
+            .map(|regex| Regex::new(regex).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn lines_are_included_and_excluded_by_regexes() {
+        let filter = Filter::new(
+            regexes(&["error", "warning"]),
+            regexes(&["^note"]),
+            0,
+            false,
+        );
+        let check = |text| filter.check(&mut stamp(0, None), text);
+        assert!(check("an error"));
+        assert!(check("a warning"));
+        assert!(!check("info"));
+        assert!(!check("note: an error"));
+        assert_eq!(2, filter.suppressed());
+
+        let filter = Filter::new(vec![], regexes(&["^note"]), 0, false);
+        assert!(filter.check(&mut stamp(0, None), "info"));
+        assert_eq!(0, filter.suppressed());
+    }
+
+    #[test]
+    fn delta_is_computed_over_all_or_shown_lines() {
+        let filter = Filter::new(regexes(&["shown"]), vec![], 0, false);
+        let mut shown = stamp(3000, Some(500));
+        filter.check(&mut stamp(1000, None), "shown");
+        filter.check(&mut stamp(2500, Some(1500)), "hidden");
+        filter.check(&mut shown, "shown");
+        assert_eq!(stamp(3000, Some(500)), shown);
+
+        let filter = Filter::new(regexes(&["shown"]), vec![], 0, true);
+        let mut first = stamp(1000, Some(1000));
+        filter.check(&mut first, "shown");
+        filter.check(&mut stamp(2500, Some(1500)), "hidden");
+        filter.check(&mut shown, "shown");
+        assert_eq!(stamp(1000, None), first);
+        assert_eq!(stamp(3000, Some(2000)), shown);
+    }
+
+    #[test]
+    fn context_lines_are_shown_without_matching_and_counted_when_suppressed() {
+        let filter = Filter::new(regexes(&["shown"]), vec![], 1, true);
+        assert_eq!(1, filter.context());
+        assert!(!filter.matches("context"));
+
+        let mut first = stamp(1000, Some(1000));
+        let mut context = stamp(2500, Some(1500));
+        filter.show(&mut first);
+        filter.suppress();
+        filter.show(&mut context);
+        assert_eq!(stamp(2500, Some(1500)), context);
+        assert_eq!(1, filter.suppressed());
+    }
+}
@@ -0,0 +1,227 @@
+use super::timestamp::{self, Timestamp};
+use crate::analysis::phase::{PhaseRule, PhaseTracker};
+use crate::analysis::{Line, Observer};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Frames of the spinner, showing that linetime is running
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Time each spinner frame is shown
+const SPINNER_FRAME: Duration = Duration::from_millis(100);
+
+/// Erases the current terminal line
+const ERASE: &str = "\r\x1b[K";
+
+/// A status line at the bottom of the terminal, on stderr, showing the elapsed time, the time
+/// since the last line, line counts and the current phase. It is erased before each line is
+/// written, and drawn again after it, by writers holding the lock for output.
+pub struct StatusLine {
+    timestamp: Arc<Mutex<Timestamp>>,
+    write_mutex: Arc<Mutex<()>>,
+    state: Mutex<State>,
+    microseconds: bool,
+}
+
+#[derive(Default)]
+struct State {
+    // Lines per stream, in the order the streams printed their first lines
+    line_counts: Vec<(String, u64)>,
+    last_line: Option<Duration>,
+    phases: PhaseTracker,
+    drawn: bool,
+    // Set when cleared at the end, to not be drawn again
+    cleared: bool,
+}
+
+impl StatusLine {
+    pub fn new(
+        timestamp: Arc<Mutex<Timestamp>>,
+        write_mutex: Arc<Mutex<()>>,
+        phase_rules: Vec<PhaseRule>,
+        microseconds: bool,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            timestamp,
+            write_mutex,
+            state: Mutex::new(State {
+                phases: PhaseTracker::new(phase_rules),
+                ..Default::default()
+            }),
+            microseconds,
+        })
+    }
+
+    /// Gets an observer counting lines and tracking phases for the status line
+    pub fn observer(self: &Arc<Self>) -> StatusObserver {
+        StatusObserver(self.clone())
+    }
+
+    /// Draws the status line, to be called when holding the lock for output
+    pub fn draw(&self) {
+        let now = self.timestamp.lock().map(|timestamp| timestamp.now());
+        let mut state = self.lock();
+        let Ok(now) = now else {
+            return;
+        };
+        if state.cleared {
+            return;
+        }
+        let text = state.text(now, self.microseconds);
+        let text = text.chars().take(terminal_width() - 1).collect::<String>();
+        let mut stderr = std::io::stderr();
+        state.drawn = write!(stderr, "{ERASE}{text}")
+            .and_then(|_| stderr.flush())
+            .is_ok();
+    }
+
+    /// Erases the status line, to be called when holding the lock for output
+    pub fn erase(&self) {
+        let mut state = self.lock();
+        if std::mem::take(&mut state.drawn) {
+            let mut stderr = std::io::stderr();
+            let _ = write!(stderr, "{ERASE}").and_then(|_| stderr.flush());
+        }
+    }
+
+    /// Draws the status line again, to update the times
+    pub fn redraw(&self) {
+        let _lock = self.write_mutex.lock().ok();
+        self.draw();
+    }
+
+    /// Erases the status line at the end, and doesn't draw it again
+    pub fn clear(&self) {
+        let _lock = self.write_mutex.lock().ok();
+        self.erase();
+        self.lock().cleared = true;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // If another thread panicked while updating, we proceed anyway
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl State {
+    /// Gets the text of the status line, e.g.,
+    /// `⠹ 01:23.456 elapsed, 00:12.012 since last line, 42 lines (out 40, err 2), phase Tests`
+    fn text(&self, now: Duration, microseconds: bool) -> String {
+        let frame = (now.as_millis() / SPINNER_FRAME.as_millis()) as usize % SPINNER.len();
+        let mut text = format!(
+            "{} {} elapsed",
+            SPINNER[frame],
+            timestamp::format(now, microseconds)
+        );
+        if let Some(last_line) = self.last_line {
+            let since = now.saturating_sub(last_line);
+            text += &format!(
+                ", {} since last line",
+                timestamp::format(since, microseconds)
+            );
+        }
+        let total = self.line_counts.iter().map(|(_, count)| count).sum::<u64>();
+        text += &match total {
+            1 => ", 1 line".to_string(),
+            total => format!(", {total} lines"),
+        };
+        if self.line_counts.len() > 1 {
+            let counts = self
+                .line_counts
+                .iter()
+                .map(|(stream, count)| format!("{stream} {count}"))
+                .collect::<Vec<_>>();
+            text += &format!(" ({})", counts.join(", "));
+        }
+        if let Some(phase) = self.phases.current() {
+            text += &format!(", phase {phase}");
+        }
+        text
+    }
+}
+
+/// Gets the number of columns of the terminal on stderr
+#[cfg(unix)]
+fn terminal_width() -> usize {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: ioctl() with TIOCGWINSZ only writes the window size
+    match unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } {
+        0 if size.ws_col > 0 => size.ws_col as usize,
+        _ => 80,
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_width() -> usize {
+    80
+}
+
+/// Counts lines and tracks phases for the status line
+pub struct StatusObserver(Arc<StatusLine>);
+
+impl Observer for StatusObserver {
+    fn line(&mut self, line: &Line) -> Option<String> {
+        let mut state = self.0.lock();
+        state.last_line = Some(line.time);
+        state.phases.line(line.time, line.text);
+        match state
+            .line_counts
+            .iter_mut()
+            .find(|(stream, _)| stream == line.stream)
+        {
+            Some((_, count)) => *count += 1,
+            None => state.line_counts.push((line.stream.to_string(), 1)),
+        }
+        None
+    }
+
+    fn end(&mut self, _time: Duration, _output: &mut dyn Write) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn text_shows_times_line_counts_and_phase() {
+        let status = StatusLine::new(
+            Arc::new(Mutex::new(Timestamp::new())),
+            Arc::new(Mutex::new(())),
+            vec!["^Testing".parse().unwrap()],
+            false,
+        );
+        assert_eq!(
+            "⠋ 00:00.000 elapsed, 0 lines",
+            status.lock().text(secs(0), false)
+        );
+
+        let mut observer = status.observer();
+        for (stream, text) in [
+            ("out", "Building"),
+            ("err", "warning"),
+            ("out", "Testing a"),
+        ] {
+            observer.line(&Line {
+                time: secs(2),
+                stream,
+                text,
+            });
+        }
+        assert_eq!(
+            "⠋ 00:05.000 elapsed, 00:03.000 since last line, 3 lines (out 2, err 1), phase Testing a",
+            status.lock().text(secs(5), false)
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use super::timestamp::{self, Stamp};
+use super::Options;
+use crate::error::MessageError;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A template for the prefix added at the start of each line. Placeholders within braces are
+/// replaced when rendering, e.g., `{elapsed} {delta} [{stream}] `. A placeholder can be given an
+/// alignment and a minimum width after a colon, e.g., `{stream:>8}` to right-align the stream
+/// name in 8 characters. Use `{{` and `}}` for literal braces and `\t` for a tab character.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Placeholder {
+    field: Field,
+    alignment: Alignment,
+    width: usize,
+}
+
+/// The values that can be used in a template
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    /// Time since start
+    Elapsed,
+    /// Time since previous line, within parentheses
+    Delta,
+    /// Time until next line, within brackets, only known when looking ahead
+    Next,
+    /// Name of the stream the line was read from
+    Stream,
+    /// Line number common for all streams
+    Line,
+    /// Line number for the stream only
+    StreamLine,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Alignment {
+    Left,
+    Right,
+    Center,
+}
+
+/// A line number. Lines unfolded from a single line, i.e., lines broken by carriage return or
+/// escape sequences rather than linefeed, get a part number after a dot, e.g., `17.2`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LineNumber {
+    pub line: u64,
+    pub part: u32,
+}
+
+impl fmt::Display for LineNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.part == 0 {
+            write!(f, "{}", self.line)
+        } else {
+            write!(f, "{}.{}", self.line, self.part)
+        }
+    }
+}
+
+/// The values placeholders are replaced with when rendering a template
+pub struct Values<'a> {
+    pub stamp: Stamp,
+    pub stream: &'a str,
+    /// Line numbers, rendered as a dash when not set, e.g., for the end line
+    pub line: Option<LineNumber>,
+    pub stream_line: Option<LineNumber>,
+    /// Time until the next line, rendered as blank space when not set
+    pub next: Option<Duration>,
+    pub microseconds: bool,
+}
+
+impl Template {
+    /// Creates a template for the default layout, e.g.,
+    /// `00:03.100 (00:00.100) [00:02.000] 17 stdout:9: `, where the delta time, the time until
+    /// the next line and line numbers are included if enabled in the options
+    pub fn default_layout(options: &Options, show_stream: bool) -> Self {
+        let mut segments = vec![Segment::Placeholder(Placeholder::new(Field::Elapsed))];
+        if options.show_delta {
+            segments.push(Segment::Text(" ".to_string()));
+            segments.push(Segment::Placeholder(Placeholder::new(Field::Delta)));
+        }
+        if options.lookahead {
+            segments.push(Segment::Text(" ".to_string()));
+            segments.push(Segment::Placeholder(Placeholder::new(Field::Next)));
+        }
+        if options.line_numbers {
+            segments.push(Segment::Text(" ".to_string()));
+            segments.push(Segment::Placeholder(Placeholder::new(Field::Line)));
+        }
+        if show_stream {
+            segments.push(Segment::Text(" ".to_string()));
+            segments.push(Segment::Placeholder(Placeholder {
+                width: options.stream_width,
+                ..Placeholder::new(Field::Stream)
+            }));
+        }
+        if options.stream_line_numbers {
+            segments.push(Segment::Text(":".to_string()));
+            segments.push(Segment::Placeholder(Placeholder::new(Field::StreamLine)));
+        }
+        segments.push(Segment::Text(": ".to_string()));
+        Self { segments }
+    }
+
+    /// Renders the template by replacing placeholders with values
+    pub fn render(&self, values: &Values) -> String {
+        let mut result = String::with_capacity(64);
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => result += text,
+                Segment::Placeholder(placeholder) => placeholder.render(values, &mut result),
+            }
+        }
+        result
+    }
+}
+
+impl FromStr for Template {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '\\' if chars.peek() == Some(&'t') => {
+                    chars.next();
+                    text.push('\t');
+                }
+                '\\' if chars.peek() == Some(&'\\') => {
+                    chars.next();
+                    text.push('\\');
+                }
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => {
+                                return Err(MessageError(format!(
+                                    "Unclosed placeholder '{{{spec}'"
+                                )))
+                            }
+                        }
+                    }
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Placeholder(spec.parse()?));
+                }
+                '}' => {
+                    return Err(MessageError(
+                        "Unmatched '}', use '}}' for a literal brace".to_string(),
+                    ))
+                }
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self { segments })
+    }
+}
+
+impl Placeholder {
+    fn new(field: Field) -> Self {
+        Self {
+            field,
+            alignment: Alignment::Left,
+            width: 0,
+        }
+    }
+
+    fn render(&self, values: &Values, result: &mut String) {
+        let value = match self.field {
+            Field::Elapsed => match values.stamp.time {
+                Some(time) => timestamp::format(time, values.microseconds),
+                None => " ".repeat(timestamp::duration_length(values.microseconds)),
+            },
+            Field::Delta => match values.stamp.delta {
+                Some(delta) => format!("({})", timestamp::format(delta, values.microseconds)),
+                // Leave blank space of the same length as a delta time
+                None => " ".repeat(timestamp::duration_length(values.microseconds) + 2),
+            },
+            Field::Next => match values.next {
+                Some(next) => format!("[{}]", timestamp::format(next, values.microseconds)),
+                None => " ".repeat(timestamp::duration_length(values.microseconds) + 2),
+            },
+            Field::Stream => values.stream.to_string(),
+            Field::Line => Self::line_number(values.line),
+            Field::StreamLine => Self::line_number(values.stream_line),
+        };
+
+        let padding = self.width.saturating_sub(value.chars().count());
+        let (before, after) = match self.alignment {
+            Alignment::Left => (0, padding),
+            Alignment::Right => (padding, 0),
+            Alignment::Center => (padding / 2, padding - padding / 2),
+        };
+        *result += " ".repeat(before).as_str();
+        *result += value.as_str();
+        *result += " ".repeat(after).as_str();
+    }
+
+    fn line_number(line_number: Option<LineNumber>) -> String {
+        line_number.map_or_else(|| "-".to_string(), |number| number.to_string())
+    }
+}
+
+impl FromStr for Placeholder {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, format) = s.split_once(':').unwrap_or((s, ""));
+        let field = match name {
+            "elapsed" => Field::Elapsed,
+            "delta" => Field::Delta,
+            "next" => Field::Next,
+            "stream" => Field::Stream,
+            "lineno" => Field::Line,
+            "stream_lineno" => Field::StreamLine,
+            _ => return Err(MessageError(format!("Unknown placeholder '{{{name}}}'"))),
+        };
+
+        let mut placeholder = Self::new(field);
+        let width = if let Some(width) = format.strip_prefix('<') {
+            width
+        } else if let Some(width) = format.strip_prefix('>') {
+            placeholder.alignment = Alignment::Right;
+            width
+        } else if let Some(width) = format.strip_prefix('^') {
+            placeholder.alignment = Alignment::Center;
+            width
+        } else {
+            format
+        };
+        if !width.is_empty() {
+            placeholder.width = width
+                .parse()
+                .map_err(|_| MessageError(format!("Invalid width in placeholder '{{{s}}}'")))?;
+        }
+        Ok(placeholder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn values(stream: &str) -> Values<'_> {
+        Values {
+            stamp: Stamp {
+                time: Some(Duration::from_millis(3100)),
+                delta: Some(Duration::from_millis(100)),
+                line: None,
+            },
+            stream,
+            line: Some(LineNumber { line: 17, part: 0 }),
+            stream_line: Some(LineNumber { line: 9, part: 2 }),
+            next: Some(Duration::from_millis(2000)),
+            microseconds: false,
+        }
+    }
+
+    fn layout_options(show_delta: bool, line_numbers: bool) -> Options {
+        Options {
+            show_delta,
+            line_numbers,
+            stream_line_numbers: line_numbers,
+            ..Default::default()
+        }
+    }
+
+    fn render(template: &str, values: &Values) -> String {
+        template.parse::<Template>().unwrap().render(values)
+    }
+
+    #[test]
+    fn default_layout_renders_timestamp_delta_and_stream() {
+        let values = values("stdout");
+        assert_eq!(
+            "00:03.100: ",
+            Template::default_layout(&layout_options(false, false), false).render(&values)
+        );
+        assert_eq!(
+            "00:03.100 (00:00.100) stdout: ",
+            Template::default_layout(&layout_options(true, false), true).render(&values)
+        );
+    }
+
+    #[test]
+    fn default_layout_pads_stream_to_width_of_longest_stream() {
+        let options = Options {
+            stream_width: 8,
+            ..layout_options(false, false)
+        };
+        assert_eq!(
+            "00:03.100 stdout  : ",
+            Template::default_layout(&options, true).render(&values("stdout"))
+        );
+    }
+
+    #[test]
+    fn default_layout_renders_time_until_next_line_when_looking_ahead() {
+        let options = Options {
+            lookahead: true,
+            ..layout_options(true, true)
+        };
+        assert_eq!(
+            "00:03.100 (00:00.100) [00:02.000] 17 stdout:9.2: ",
+            Template::default_layout(&options, true).render(&values("stdout"))
+        );
+    }
+
+    #[test]
+    fn default_layout_renders_line_numbers() {
+        let values = values("stdout");
+        assert_eq!(
+            "00:03.100 17 stdout:9.2: ",
+            Template::default_layout(&layout_options(false, true), true).render(&values)
+        );
+        assert_eq!(
+            "00:03.100 17:9.2: ",
+            Template::default_layout(&layout_options(false, true), false).render(&values)
+        );
+    }
+
+    #[test]
+    fn missing_line_numbers_are_rendered_as_dash() {
+        let values = Values {
+            line: None,
+            stream_line: None,
+            ..values("------")
+        };
+        assert_eq!(
+            "- ------:-",
+            render("{lineno} {stream}:{stream_lineno}", &values)
+        );
+    }
+
+    #[test]
+    fn missing_delta_and_timestamp_are_rendered_as_spaces() {
+        let values = Values {
+            stamp: Stamp::default(),
+            stream: "",
+            line: None,
+            stream_line: None,
+            next: None,
+            microseconds: true,
+        };
+        assert_eq!(
+            format!("{}: ", " ".repeat(12 + 1 + 14)),
+            Template::default_layout(&layout_options(true, false), false).render(&values)
+        );
+        let options = Options {
+            lookahead: true,
+            ..layout_options(true, false)
+        };
+        assert_eq!(
+            format!("{}: ", " ".repeat(12 + 1 + 14 + 1 + 14)),
+            Template::default_layout(&options, false).render(&values)
+        );
+    }
+
+    #[test]
+    fn placeholders_are_replaced_and_text_is_kept() {
+        assert_eq!(
+            "00:03.100\t(00:00.100)\t[stdout] ",
+            render("{elapsed}\\t{delta}\\t[{stream}] ", &values("stdout"))
+        );
+    }
+
+    #[test]
+    fn time_until_next_line_is_rendered_within_brackets() {
+        assert_eq!("[00:02.000] x", render("{next} x", &values("stdout")));
+        let values = Values {
+            next: None,
+            ..values("stdout")
+        };
+        assert_eq!(format!("{} x", " ".repeat(11)), render("{next} x", &values));
+    }
+
+    #[test]
+    fn placeholders_can_be_aligned_within_width() {
+        assert_eq!("[err   ]", render("[{stream:6}]", &values("err")));
+        assert_eq!("[err   ]", render("[{stream:<6}]", &values("err")));
+        assert_eq!("[   err]", render("[{stream:>6}]", &values("err")));
+        assert_eq!("[ err  ]", render("[{stream:^6}]", &values("err")));
+        assert_eq!("[stderr]", render("[{stream:>3}]", &values("stderr")));
+    }
+
+    #[test]
+    fn double_braces_and_backslashes_are_literal() {
+        assert_eq!(
+            "{stdout} \\",
+            render("{{{stream}}} \\\\", &values("stdout"))
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        assert_eq!(
+            Err(MessageError("Unknown placeholder '{time}'".to_string())),
+            "{time}".parse::<Template>()
+        );
+        assert_eq!(
+            Err(MessageError("Unclosed placeholder '{stream'".to_string())),
+            "{stream".parse::<Template>()
+        );
+        assert_eq!(
+            Err(MessageError(
+                "Invalid width in placeholder '{stream:>x}'".to_string()
+            )),
+            "{stream:>x}".parse::<Template>()
+        );
+        assert!("stream}".parse::<Template>().is_err());
+    }
+}
@@ -9,6 +9,11 @@ use std::{
 
 pub struct Timestamp {
     previous_time: Option<Duration>,
+    // Number of lines started, kept together with the time to get line numbers in the same order
+    // as timestamps
+    line_count: u64,
+    // Time used instead of the clock, e.g., when replaying a recording
+    fixed_time: Option<Duration>,
     #[cfg(not(test))]
     start_time: SystemTime,
     #[cfg(test)]
@@ -20,6 +25,8 @@ impl Timestamp {
     pub fn new() -> Self {
         Self {
             previous_time: None,
+            line_count: 0,
+            fixed_time: None,
             #[cfg(not(test))]
             start_time: SystemTime::now(),
             #[cfg(test)]
@@ -28,23 +35,53 @@ impl Timestamp {
     }
 
     pub fn get(&mut self) -> Duration {
+        let t = if let Some(fixed_time) = self.fixed_time {
+            fixed_time
+        } else {
+            #[cfg(not(test))]
+            let t = SystemTime::now()
+                .duration_since(self.start_time)
+                .expect("Start time should be earlier than get");
+            #[cfg(test)]
+            let t = self
+                .expected_stamps
+                .pop_front()
+                .expect("Unexpected request for timestamp");
+            t
+        };
+        self.previous_time = Some(t);
+        t
+    }
+
+    /// Sets a fixed time to use instead of the clock, e.g., when replaying a recording
+    pub fn set(&mut self, time: Duration) {
+        self.fixed_time = Some(time);
+    }
+
+    /// Gets the current time without affecting the previous time, i.e., delta times
+    pub fn now(&self) -> Duration {
+        if let Some(fixed_time) = self.fixed_time {
+            return fixed_time;
+        }
         #[cfg(not(test))]
-        let t = SystemTime::now()
+        return SystemTime::now()
             .duration_since(self.start_time)
-            .expect("Start time should be earlier than get");
+            .expect("Start time should be earlier than now");
+        // Fake current time is the previous timestamp
         #[cfg(test)]
-        let t = self
-            .expected_stamps
-            .pop_front()
-            .expect("Unexpected request for timestamp");
-        self.previous_time = Some(t);
-        t
+        return self.previous_time.unwrap_or_default();
     }
 
     pub fn previous(&self) -> Option<Duration> {
         self.previous_time
     }
 
+    /// Counts a new line and returns its line number, starting from 1
+    pub fn count_line(&mut self) -> u64 {
+        self.line_count += 1;
+        self.line_count
+    }
+
     #[cfg(test)]
     pub fn expect_get(&mut self, stamp: Duration) {
         self.expected_stamps.push_back(stamp);
@@ -60,30 +97,35 @@ impl Timestamp {
     }
 }
 
-/// Gets a timestamp and creates a string suitable for prefixing output lines
-pub fn create_prefix(
-    timestamp: &Arc<Mutex<Timestamp>>,
-    with_delta: bool,
-    microseconds: bool,
-) -> String {
+/// A timestamp taken for a line, together with the time since the previous line
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stamp {
+    /// Time since start, None if no timestamp could be taken
+    pub time: Option<Duration>,
+    /// Time since previous line, None for the first line
+    pub delta: Option<Duration>,
+    /// Line number common for all streams, None if no new line was counted
+    pub line: Option<u64>,
+}
+
+/// Gets a timestamp, together with the delta time to the previous timestamp. If `new_line` is set,
+/// a new line is also counted and its number is included.
+pub fn take(timestamp: &Arc<Mutex<Timestamp>>, new_line: bool) -> Stamp {
     let Ok(mut guard) = timestamp.lock() else {
-        // If other thread has panicked, we return a string of correct length with spaces instead
-        return " ".repeat(stamp_length(with_delta, microseconds));
+        // If other thread has panicked, we return an empty stamp which is printed as spaces
+        return Stamp::default();
     };
     let previous_time = guard.previous();
     let time = guard.get();
+    let line = new_line.then(|| guard.count_line());
     drop(guard);
 
-    let mut result = format(time, microseconds);
-    if with_delta {
-        result += if let Some(previous_time) = previous_time {
-            format!(" ({})", format(time - previous_time, microseconds))
-        } else {
-            " ".repeat(duration_length(microseconds) + 3)
-        }
-        .as_str();
+    Stamp {
+        time: Some(time),
+        // A fixed time, e.g., from a recording, could be set earlier than the previous time
+        delta: previous_time.map(|previous_time| time.saturating_sub(previous_time)),
+        line,
     }
-    result
 }
 
 fn subsec_length(microseconds: bool) -> usize {
@@ -95,20 +137,13 @@ fn subsec_length(microseconds: bool) -> usize {
 }
 
 /// The string length of a single duration string, when no hour field is present
-fn duration_length(microseconds: bool) -> usize {
+pub fn duration_length(microseconds: bool) -> usize {
     2 + 1 + 2 + 1 + subsec_length(microseconds)
 }
 
-/// The string length of a complete timestamp string, when no hour field is present
-fn stamp_length(with_delta: bool, microseconds: bool) -> usize {
-    if with_delta {
-        duration_length(microseconds) * 2 + 3
-    } else {
-        duration_length(microseconds)
-    }
-}
-
-fn format(duration: Duration, microseconds: bool) -> String {
+/// Formats a duration as `MM:SS.mmm`, or `MM:SS.uuuuuu` with microseconds. An hour field is
+/// added in front when necessary.
+pub fn format(duration: Duration, microseconds: bool) -> String {
     let mut s = String::with_capacity(20);
     let hours = duration.as_secs() / (60 * 60);
     let minutes = duration.as_secs() / 60 % 60;
@@ -178,6 +213,17 @@ mod tests {
         assert_eq!(Some(ms(2345)), t.previous());
     }
 
+    #[test]
+    fn fixed_time_is_used_instead_of_clock() {
+        let mut t = Timestamp::new();
+        t.set(ms(1234));
+        assert_eq!(ms(1234), t.get());
+        assert_eq!(ms(1234), t.now());
+        t.set(ms(2345));
+        assert_eq!(ms(2345), t.get());
+        assert_eq!(Some(ms(2345)), t.previous());
+    }
+
     #[test]
     #[should_panic(expected = "Unexpected request for timestamp")]
     fn get_more_than_available_panics() {
@@ -193,6 +239,52 @@ mod tests {
         t.assert_all_used();
     }
 
+    #[test]
+    fn take_gives_delta_from_previous_stamp() {
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        timestamp.lock().unwrap().expect_get(ms(1234));
+        timestamp.lock().unwrap().expect_get(ms(2345));
+
+        assert_eq!(
+            Stamp {
+                time: Some(ms(1234)),
+                delta: None,
+                line: None,
+            },
+            take(&timestamp, false)
+        );
+        assert_eq!(
+            Stamp {
+                time: Some(ms(2345)),
+                delta: Some(ms(1111)),
+                line: None,
+            },
+            take(&timestamp, false)
+        );
+    }
+
+    #[test]
+    fn take_gives_zero_delta_for_fixed_time_set_backwards() {
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        timestamp.lock().unwrap().set(ms(2000));
+        take(&timestamp, false);
+        timestamp.lock().unwrap().set(ms(1000));
+
+        assert_eq!(Some(Duration::ZERO), take(&timestamp, false).delta);
+    }
+
+    #[test]
+    fn take_counts_new_lines() {
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        timestamp.lock().unwrap().expect_get(ms(1));
+        timestamp.lock().unwrap().expect_get(ms(2));
+        timestamp.lock().unwrap().expect_get(ms(3));
+
+        assert_eq!(Some(1), take(&timestamp, true).line);
+        assert_eq!(None, take(&timestamp, false).line);
+        assert_eq!(Some(2), take(&timestamp, true).line);
+    }
+
     #[test]
     fn format_duration_with_millisecond_precision() {
         assert_eq!("00:00.000", format(Duration::ZERO, false));
@@ -0,0 +1,439 @@
+use crate::analysis::{Line, Observers};
+use crate::error::MessageError;
+use regex::Regex;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Regex to catch the prefix of lines in linetime output with the default layout, i.e., a
+/// timestamp, an optional delta time, optional line numbers and an optional stream name
+static LINE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    const DURATION: &str = r"(?:[0-9]+:)?[0-9]{2}:[0-9]{2}\.(?:[0-9]{6}|[0-9]{3})";
+    const LINE_NUMBER: &str = r"[0-9]+(?:\.[0-9]+)?|-";
+    Regex::new(
+        format!(
+            concat!(
+                r"^(?<elapsed>{duration})",
+                // The delta time of the first line is blank space
+                r"(?: \((?<delta>{duration})\)| {{15}}| {{12}})?",
+                // The time until the next line, when looking ahead
+                r"(?: \[(?<next>{duration})\])?",
+                r"(?: (?<lineno>{line_number}))?",
+                r"(?: (?<stream>[^ :]+))?",
+                r"(?::(?<stream_lineno>{line_number}))?",
+                r": (?<text>.*)$"
+            ),
+            duration = DURATION,
+            line_number = LINE_NUMBER
+        )
+        .as_str(),
+    )
+    .expect("Line regex should be valid")
+});
+
+/// Text of the line added when all streams have ended
+const END_TEXT: &str = "\u{23f1} End";
+
+/// A line of linetime output, parsed into its parts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParsedLine {
+    /// Time since start, None if the line has no timestamp
+    pub time: Option<Duration>,
+    pub delta: Option<Duration>,
+    /// Time until the next line, if printed when looking ahead
+    pub next: Option<Duration>,
+    /// Line numbers as printed, e.g., `17.2` for the second part of an unfolded line
+    pub line: Option<String>,
+    pub stream_line: Option<String>,
+    /// Name of the stream, empty if unknown
+    pub stream: String,
+    pub text: String,
+    /// Set for the line added when all streams have ended
+    pub end: bool,
+}
+
+impl ParsedLine {
+    /// Parses a line of linetime output. A line not starting with a timestamp is kept as text
+    /// without timestamp.
+    pub fn parse(line: &str) -> Self {
+        let Some(captures) = LINE_REGEX.captures(line) else {
+            return Self {
+                text: line.to_string(),
+                ..Default::default()
+            };
+        };
+        let string = |name| captures.name(name).map(|m| m.as_str().to_string());
+        let duration = |name| captures.name(name).and_then(|m| parse_duration(m.as_str()));
+
+        let stream = string("stream").unwrap_or_default();
+        let text = string("text").unwrap_or_default();
+        // The end line has dashes instead of a stream name, and possibly a count of suppressed
+        // lines after the text
+        let end = text.starts_with(END_TEXT) && stream.chars().all(|c| c == '-');
+        Self {
+            time: duration("elapsed"),
+            delta: duration("delta"),
+            next: duration("next"),
+            line: string("lineno").filter(|line| line != "-"),
+            stream_line: string("stream_lineno").filter(|line| line != "-"),
+            stream: if end { String::new() } else { stream },
+            text: if end { String::new() } else { text },
+            end,
+        }
+    }
+
+    /// Creates a line from a JSON object, as written by `to_json`
+    pub fn from_json(json: &str) -> std::io::Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(json)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+        let string = |name: &str| value[name].as_str().map(str::to_string);
+        let duration = |name: &str| {
+            value[name]
+                .as_f64()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        };
+        Ok(Self {
+            time: duration("elapsed"),
+            delta: duration("delta"),
+            next: duration("next"),
+            line: string("lineno"),
+            stream_line: string("stream_lineno"),
+            stream: string("stream").unwrap_or_default(),
+            text: string("text").unwrap_or_default(),
+            end: value["end"].as_bool().unwrap_or_default(),
+        })
+    }
+
+    /// Gets the line as a JSON object, with times in seconds
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "elapsed": self.time.map(|time| time.as_secs_f64()),
+            "delta": self.delta.map(|delta| delta.as_secs_f64()),
+            "next": self.next.map(|next| next.as_secs_f64()),
+            "lineno": self.line,
+            "stream": self.stream,
+            "stream_lineno": self.stream_line,
+            "text": self.text,
+            "end": self.end,
+        })
+        .to_string()
+    }
+
+    /// Gets the line as a CSV row, with the columns in `CSV_HEADER`
+    pub fn to_csv(&self) -> String {
+        let seconds = |duration: Option<Duration>| {
+            duration.map_or_else(String::new, |d| format!("{:.6}", d.as_secs_f64()))
+        };
+        [
+            seconds(self.time),
+            seconds(self.delta),
+            seconds(self.next),
+            self.line.clone().unwrap_or_default(),
+            csv_field(&self.stream),
+            self.stream_line.clone().unwrap_or_default(),
+            csv_field(&self.text),
+            self.end.to_string(),
+        ]
+        .join(",")
+    }
+}
+
+pub const CSV_HEADER: &str = "elapsed,delta,next,lineno,stream,stream_lineno,text,end";
+
+/// Quotes a CSV field if necessary
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Parses a duration formatted by `timestamp::format`, with or without hour field and with
+/// milliseconds or microseconds
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (time, fraction) = s.split_once('.')?;
+    let seconds = time.split(':').try_fold(0, |total, field| {
+        Some(total * 60 + field.parse::<u64>().ok()?)
+    })?;
+    let nanos = fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32);
+    Some(Duration::new(seconds, nanos))
+}
+
+/// Output format for parsed lines
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err(MessageError(format!(
+                "Unknown format '{s}', expected 'jsonl' or 'csv'"
+            ))),
+        }
+    }
+}
+
+/// Reads lines from input, with invalid UTF-8 replaced, and calls a function for each line
+pub fn for_each_line(
+    mut input: impl BufRead,
+    mut f: impl FnMut(&str) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut buffer = vec![];
+    loop {
+        buffer.clear();
+        if input.read_until(b'\n', &mut buffer)? == 0 {
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(&buffer);
+        f(line.trim_end_matches(['\n', '\r']))?;
+    }
+}
+
+/// Parses linetime output and writes the parsed lines in a structured format. Lines with a
+/// timestamp are also observed, and the summaries of the observers are written at the end, at the
+/// time of the end line or else of the last line.
+pub fn convert(
+    input: impl BufRead,
+    output: &mut impl Write,
+    format: Format,
+    observers: &Observers,
+    summary: &mut dyn Write,
+) -> std::io::Result<()> {
+    if format == Format::Csv {
+        writeln!(output, "{CSV_HEADER}")?;
+    }
+    let mut end_time = Duration::ZERO;
+    for_each_line(input, |line| {
+        let line = ParsedLine::parse(line);
+        if let Some(time) = line.time {
+            end_time = time;
+            if !line.end {
+                observers.line(&Line {
+                    time,
+                    stream: &line.stream,
+                    text: &line.text,
+                });
+            }
+        }
+        match format {
+            Format::Jsonl => writeln!(output, "{}", line.to_json()),
+            Format::Csv => writeln!(output, "{}", line.to_csv()),
+        }
+    })?;
+    output.flush()?;
+    observers.end(end_time, summary)?;
+    summary.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Observer;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn line(time: u64, delta: Option<u64>, stream: &str, text: &str) -> ParsedLine {
+        ParsedLine {
+            time: Some(ms(time)),
+            delta: delta.map(ms),
+            stream: stream.to_string(),
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn durations_are_parsed_with_and_without_hours() {
+        assert_eq!(Some(ms(83_100)), parse_duration("01:23.100"));
+        assert_eq!(
+            Some(Duration::from_micros(83_100_200)),
+            parse_duration("01:23.100200")
+        );
+        assert_eq!(
+            Some(ms(2 * 3_600_000 + 1000)),
+            parse_duration("02:00:01.000")
+        );
+        assert_eq!(None, parse_duration("01:23"));
+    }
+
+    #[test]
+    fn lines_with_stream_and_delta_are_parsed() {
+        assert_eq!(
+            line(3100, None, "", "hello: world"),
+            ParsedLine::parse("00:03.100: hello: world")
+        );
+        assert_eq!(
+            line(3100, None, "stdout", "hello"),
+            ParsedLine::parse("00:03.100 stdout: hello")
+        );
+        assert_eq!(
+            line(3100, Some(100), "stderr", "hello"),
+            ParsedLine::parse("00:03.100 (00:00.100) stderr: hello")
+        );
+        assert_eq!(
+            line(3100, None, "stderr", "hello"),
+            ParsedLine::parse("00:03.100             stderr: hello")
+        );
+        assert_eq!(
+            line(3100, None, "", " hello"),
+            ParsedLine::parse("00:03.100000               :  hello")
+        );
+    }
+
+    #[test]
+    fn line_numbers_are_parsed() {
+        let parsed = ParsedLine::parse("00:03.100 (00:00.100) 17 stdout:9.2: hello");
+        assert_eq!(Some("17".to_string()), parsed.line);
+        assert_eq!(Some("9.2".to_string()), parsed.stream_line);
+        assert_eq!("stdout", parsed.stream);
+        assert_eq!(
+            Some("3".to_string()),
+            ParsedLine::parse("00:03.100:3: x").stream_line
+        );
+    }
+
+    #[test]
+    fn time_until_next_line_is_parsed() {
+        let parsed = ParsedLine::parse("00:03.100 (00:00.100) [00:02.000] 17 stdout: hello");
+        assert_eq!(Some(ms(100)), parsed.delta);
+        assert_eq!(Some(ms(2000)), parsed.next);
+        assert_eq!(Some("17".to_string()), parsed.line);
+        assert_eq!("hello", parsed.text);
+    }
+
+    #[test]
+    fn end_line_is_detected() {
+        let parsed = ParsedLine::parse("00:04.000 (00:01.750) - ------: \u{23f1} End");
+        assert!(parsed.end);
+        assert_eq!(Some(ms(4000)), parsed.time);
+        assert_eq!(None, parsed.line);
+        assert_eq!("", parsed.stream);
+        assert!(!ParsedLine::parse("00:04.000 stdout: \u{23f1} End").end);
+        assert!(ParsedLine::parse("00:04.000 ------: \u{23f1} End (3 lines suppressed)").end);
+    }
+
+    #[test]
+    fn lines_without_timestamp_are_kept_as_text() {
+        assert_eq!(
+            ParsedLine {
+                text: "not linetime: output".to_string(),
+                ..Default::default()
+            },
+            ParsedLine::parse("not linetime: output")
+        );
+    }
+
+    #[test]
+    fn lines_are_converted_to_jsonl_and_back() {
+        let mut output = vec![];
+        convert(
+            "00:01.500 (00:00.500) 2 stdout: \"quoted\"\r\n".as_bytes(),
+            &mut output,
+            Format::Jsonl,
+            &Observers::default(),
+            &mut std::io::sink(),
+        )
+        .unwrap();
+        let json = String::from_utf8(output).unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"delta":0.5,"elapsed":1.5,"end":false,"lineno":"2","next":null,"#,
+                r#""stream":"stdout","stream_lineno":null,"text":"\"quoted\""}"#,
+                "\n"
+            ),
+            json
+        );
+        assert_eq!(
+            ParsedLine {
+                line: Some("2".to_string()),
+                ..line(1500, Some(500), "stdout", "\"quoted\"")
+            },
+            ParsedLine::from_json(json.trim_end()).unwrap()
+        );
+    }
+
+    #[test]
+    fn lines_are_converted_to_csv() {
+        let mut output = vec![];
+        convert(
+            "00:01.500 stderr: a, \"b\"\nplain\n".as_bytes(),
+            &mut output,
+            Format::Csv,
+            &Observers::default(),
+            &mut std::io::sink(),
+        )
+        .unwrap();
+        assert_eq!(
+            concat!(
+                "elapsed,delta,next,lineno,stream,stream_lineno,text,end\n",
+                "1.500000,,,,stderr,,\"a, \"\"b\"\"\",false\n",
+                ",,,,,,plain,false\n"
+            ),
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    /// Observer recording the observed lines and the end time in its summary
+    struct Recorder(Vec<String>);
+
+    impl Observer for Recorder {
+        fn line(&mut self, line: &Line) -> Option<String> {
+            self.0
+                .push(format!("{:?} {}: {}", line.time, line.stream, line.text));
+            None
+        }
+
+        fn end(&mut self, time: Duration, output: &mut dyn Write) -> std::io::Result<()> {
+            writeln!(output, "{} at {time:?}", self.0.join(", "))
+        }
+    }
+
+    #[test]
+    fn lines_with_timestamp_are_observed() {
+        let observers = Observers::default();
+        observers.add(Recorder(vec![]));
+        let mut summary = vec![];
+        convert(
+            concat!(
+                "00:01.500 stdout: a\n",
+                "plain\n",
+                "00:02.000 (00:00.500): b\n",
+                "00:03.000 (00:01.000) ---: \u{23f1} End\n"
+            )
+            .as_bytes(),
+            &mut std::io::sink(),
+            Format::Jsonl,
+            &observers,
+            &mut summary,
+        )
+        .unwrap();
+        assert_eq!(
+            "1.5s stdout: a, 2s : b at 3s\n",
+            String::from_utf8(summary).unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_format_is_rejected() {
+        assert_eq!(Ok(Format::Csv), "csv".parse());
+        assert_eq!(
+            Err(MessageError(
+                "Unknown format 'xml', expected 'jsonl' or 'csv'".to_string()
+            )),
+            "xml".parse::<Format>()
+        );
+    }
+}
@@ -0,0 +1,251 @@
+pub mod cast;
+
+use crate::error::{Result, ResultExt};
+use crate::output::timestamp::Timestamp;
+use crate::parse::{self, ParsedLine};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use self::cast::CastWriter;
+
+/// Output read from a stream at a point in time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub time: Duration,
+    /// Name of the stream, empty if unknown
+    pub stream: String,
+    pub data: String,
+}
+
+/// A recorded session, e.g., read from a cast file
+#[derive(Debug, Default, PartialEq)]
+pub struct Recording {
+    pub events: Vec<Event>,
+    /// Time and exit code of the executed command, if recorded
+    pub exit: Option<(Duration, i32)>,
+    /// Time when all streams ended, if known without exit code
+    pub end: Option<Duration>,
+}
+
+impl Recording {
+    /// Loads a recording from a file
+    pub fn load(path: &str) -> Result<Self> {
+        let file =
+            std::fs::File::open(path).error_context(format!("Failed to open file '{path}'"))?;
+        Self::read(std::io::BufReader::new(file))
+            .error_context(format!("Failed to read recording '{path}'"))
+    }
+
+    /// Reads a recording in asciinema cast format, or linetime output either as text or as
+    /// parsed JSONL
+    pub fn read(mut input: impl BufRead) -> std::io::Result<Self> {
+        let mut content = vec![];
+        input.read_to_end(&mut content)?;
+        let first_line = content
+            .split(|&byte| byte == b'\n')
+            .find(|line| !line.trim_ascii().is_empty())
+            .unwrap_or_default();
+        if first_line.starts_with(b"{") {
+            let header = serde_json::from_slice::<serde_json::Value>(first_line);
+            if header.is_ok_and(|header| header.get("version").is_some()) {
+                return cast::read(content.as_slice());
+            }
+            let mut lines = vec![];
+            parse::for_each_line(content.as_slice(), |line| {
+                if !line.trim().is_empty() {
+                    lines.push(ParsedLine::from_json(line)?);
+                }
+                Ok(())
+            })?;
+            Ok(Self::from_lines(lines))
+        } else {
+            let mut lines = vec![];
+            parse::for_each_line(content.as_slice(), |line| {
+                lines.push(ParsedLine::parse(line));
+                Ok(())
+            })?;
+            Ok(Self::from_lines(lines))
+        }
+    }
+
+    /// Creates a recording from parsed linetime output. Lines without timestamp get the time of
+    /// the previous line. Lines of different streams can be printed out of order, so a time
+    /// earlier than the previous line is also replaced by the time of the previous line.
+    pub fn from_lines(lines: Vec<ParsedLine>) -> Self {
+        let mut recording = Self::default();
+        let mut time = Duration::ZERO;
+        for line in lines {
+            time = line.time.map_or(time, |line_time| line_time.max(time));
+            if line.end {
+                recording.end = Some(time);
+            } else {
+                recording.events.push(Event {
+                    time,
+                    stream: line.stream,
+                    data: line.text + "\n",
+                });
+            }
+        }
+        recording
+    }
+
+    /// Gets the time of the last event, exit or end
+    pub fn end_time(&self) -> Duration {
+        let last_event = self.events.last().map(|event| event.time);
+        let exit = self.exit.map(|(time, _)| time);
+        last_event.max(exit).max(self.end).unwrap_or_default()
+    }
+}
+
+/// Records raw input from streams, with the time it was read, to an asciinema cast file. The
+/// recorder is cloned to be shared by the streams and implements Write to be used as a copy of
+/// the input.
+#[derive(Clone)]
+pub struct Recorder {
+    writer: Arc<Mutex<CastWriter>>,
+    // Common Timestamp to get the same times as when printing lines
+    timestamp: Arc<Mutex<Timestamp>>,
+}
+
+impl Recorder {
+    /// Creates a cast file recording the executed command, if any
+    pub fn create(
+        path: &str,
+        timestamp: Arc<Mutex<Timestamp>>,
+        command: &[String],
+    ) -> Result<Self> {
+        let file =
+            std::fs::File::create(path).error_context(format!("Failed to create file '{path}'"))?;
+        let writer = CastWriter::new(
+            Box::new(std::io::BufWriter::new(file)),
+            &cast::Header::from_environment(command),
+        )
+        .error_context("Failed to write cast header")?;
+        Ok(Self {
+            writer: Arc::new(Mutex::new(writer)),
+            timestamp,
+        })
+    }
+
+    /// Records the exit code of the executed command
+    pub fn exit(&self, code: i32) -> Result<()> {
+        let time = self.now();
+        self.lock()
+            .exit(time, code)
+            .error_context("Failed to write exit to cast file")
+    }
+
+    /// Writes all recorded output to the file
+    pub fn finish(&self) -> Result<()> {
+        self.lock()
+            .flush()
+            .error_context("Failed to write to cast file")
+    }
+
+    fn now(&self) -> std::time::Duration {
+        self.timestamp
+            .lock()
+            .map(|timestamp| timestamp.now())
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CastWriter> {
+        // If another thread panicked while recording, we proceed anyway
+        self.writer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let time = self.now();
+        self.lock().output(time, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(millis: u64, stream: &str, data: &str) -> Event {
+        Event {
+            time: Duration::from_millis(millis),
+            stream: stream.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn linetime_output_is_read_as_recording() {
+        let text = concat!(
+            "00:01.500 stdout: hello\n",
+            "no timestamp\n",
+            "00:02.250 (00:00.750) stderr: world\n",
+            "00:04.000 (00:01.750) ------: \u{23f1} End\n"
+        );
+        assert_eq!(
+            Recording {
+                events: vec![
+                    event(1500, "stdout", "hello\n"),
+                    event(1500, "", "no timestamp\n"),
+                    event(2250, "stderr", "world\n")
+                ],
+                exit: None,
+                end: Some(Duration::from_millis(4000)),
+            },
+            Recording::read(text.as_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn times_of_recording_never_decrease() {
+        let text = concat!(
+            "00:00.303 stderr: Step 1\n",
+            "00:00.001 stdout: Building\n",
+            "00:00.806 ------: \u{23f1} End\n"
+        );
+        assert_eq!(
+            vec![
+                event(303, "stderr", "Step 1\n"),
+                event(303, "stdout", "Building\n")
+            ],
+            Recording::read(text.as_bytes()).unwrap().events
+        );
+    }
+
+    #[test]
+    fn times_of_cast_recording_never_decrease() {
+        let text = concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[2.0, \"o\", \"a\\n\"]\n",
+            "[1.0, \"o\", \"b\\n\"]\n",
+            "[0.5, \"x\", \"0\"]\n"
+        );
+        let recording = Recording::read(text.as_bytes()).unwrap();
+        assert_eq!(
+            vec![event(2000, "", "a\n"), event(2000, "", "b\n")],
+            recording.events
+        );
+        assert_eq!(Some((Duration::from_millis(2000), 0)), recording.exit);
+    }
+
+    #[test]
+    fn parsed_jsonl_is_read_as_recording() {
+        let text = concat!(
+            r#"{"elapsed":1.5,"stream":"stdout","text":"hello","end":false}"#,
+            "\n",
+            r#"{"elapsed":4.0,"stream":"","text":"","end":true}"#,
+            "\n"
+        );
+        let recording = Recording::read(text.as_bytes()).unwrap();
+        assert_eq!(vec![event(1500, "stdout", "hello\n")], recording.events);
+        assert_eq!(Duration::from_millis(4000), recording.end_time());
+    }
+}
@@ -0,0 +1,314 @@
+use super::{Event, Recording};
+use std::io::{BufRead, Write};
+use std::time::{Duration, SystemTime};
+
+/// Output read within this interval is merged into a single event, since input is read a
+/// character at a time
+const MERGE_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Header of an asciinema v2 cast file, see
+/// <https://docs.asciinema.org/manual/asciicast/v2/> for reference
+#[derive(Debug, PartialEq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    /// Unix timestamp of the start of the recording
+    pub timestamp: u64,
+    pub command: Option<String>,
+    pub env: Vec<(String, String)>,
+}
+
+impl Header {
+    /// Creates a header with terminal size and environment variables from the environment
+    pub fn from_environment(command: &[String]) -> Self {
+        let variable = |name: &str, default: u32| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            width: variable("COLUMNS", 80),
+            height: variable("LINES", 24),
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            command: (!command.is_empty()).then(|| command.join(" ")),
+            env: ["SHELL", "TERM"]
+                .into_iter()
+                .filter_map(|name| {
+                    std::env::var(name)
+                        .ok()
+                        .map(|value| (name.to_string(), value))
+                })
+                .collect(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut header = serde_json::json!({
+            "version": 2,
+            "width": self.width,
+            "height": self.height,
+            "timestamp": self.timestamp,
+            "env": self
+                .env
+                .iter()
+                .map(|(name, value)| (name.clone(), value.as_str().into()))
+                .collect::<serde_json::Map<_, _>>(),
+        });
+        if let Some(command) = &self.command {
+            header["command"] = command.as_str().into();
+        }
+        header.to_string()
+    }
+}
+
+/// Writes an asciinema v2 cast file, i.e., a header line followed by one line per event
+pub struct CastWriter {
+    output: Box<dyn Write + Send>,
+    // Output not yet written as an event, with the time it was first read
+    pending: Option<(Duration, Vec<u8>)>,
+}
+
+impl CastWriter {
+    pub fn new(mut output: Box<dyn Write + Send>, header: &Header) -> std::io::Result<Self> {
+        writeln!(output, "{}", header.to_json())?;
+        Ok(Self {
+            output,
+            pending: None,
+        })
+    }
+
+    /// Records output read at a point in time
+    pub fn output(&mut self, time: Duration, data: &[u8]) -> std::io::Result<()> {
+        match &mut self.pending {
+            Some((start, bytes)) if time.saturating_sub(*start) < MERGE_INTERVAL => {
+                bytes.extend_from_slice(data);
+            }
+            _ => {
+                let mut bytes = self.write_pending(false)?;
+                bytes.extend_from_slice(data);
+                self.pending = Some((time, bytes));
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the exit code of the executed command
+    pub fn exit(&mut self, time: Duration, code: i32) -> std::io::Result<()> {
+        self.write_pending(true)?;
+        self.write_event(time, "x", code.to_string().as_str())?;
+        self.output.flush()
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.write_pending(true)?;
+        self.output.flush()
+    }
+
+    /// Writes pending output as an event. Unless all output should be written, bytes at the end
+    /// not making up a complete UTF-8 character are returned to be written with later output.
+    fn write_pending(&mut self, all: bool) -> std::io::Result<Vec<u8>> {
+        let Some((time, mut bytes)) = self.pending.take() else {
+            return Ok(vec![]);
+        };
+        let complete_length = match std::str::from_utf8(&bytes) {
+            Err(error) if !all && error.error_len().is_none() => error.valid_up_to(),
+            _ => bytes.len(),
+        };
+        let rest = bytes.split_off(complete_length);
+        if !bytes.is_empty() {
+            self.write_event(time, "o", String::from_utf8_lossy(&bytes).as_ref())?;
+        }
+        Ok(rest)
+    }
+
+    fn write_event(&mut self, time: Duration, code: &str, data: &str) -> std::io::Result<()> {
+        writeln!(
+            self.output,
+            "[{:.6}, {}, {}]",
+            time.as_secs_f64(),
+            serde_json::Value::from(code),
+            serde_json::Value::from(data)
+        )
+    }
+}
+
+/// Reads a cast file. Output events and the exit event are read, other events are ignored. A time
+/// earlier than the previous event, e.g., in an edited file, is replaced by the previous time.
+pub fn read(input: impl BufRead) -> std::io::Result<Recording> {
+    let invalid = |line_number: usize, message: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Line {line_number}: {message}"),
+        )
+    };
+
+    let mut recording = Recording::default();
+    let mut previous = Duration::ZERO;
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: serde_json::Value = serde_json::from_str(line.as_str())
+            .map_err(|error| invalid(index + 1, error.to_string().as_str()))?;
+        if index == 0 {
+            if value["version"] != 2 {
+                return Err(invalid(1, "Not an asciinema v2 cast header"));
+            }
+            continue;
+        }
+
+        let (Some(time), Some(code), Some(data)) =
+            (value[0].as_f64(), value[1].as_str(), value[2].as_str())
+        else {
+            return Err(invalid(index + 1, "Expected event as [time, code, data]"));
+        };
+        let time = Duration::try_from_secs_f64(time)
+            .map_err(|error| invalid(index + 1, error.to_string().as_str()))?
+            .max(previous);
+        previous = time;
+        match code {
+            "o" => recording.events.push(Event {
+                time,
+                stream: String::new(),
+                data: data.to_string(),
+            }),
+            "x" => {
+                let code = data
+                    .parse()
+                    .map_err(|_| invalid(index + 1, "Invalid exit code"))?;
+                recording.exit = Some((time, code));
+            }
+            _ => {}
+        }
+    }
+    Ok(recording)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SharedVec;
+
+    fn header() -> Header {
+        Header {
+            width: 100,
+            height: 50,
+            timestamp: 1234,
+            command: Some("make all".to_string()),
+            env: vec![("TERM".to_string(), "xterm".to_string())],
+        }
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn header_is_written_first() {
+        let output = SharedVec::default();
+        CastWriter::new(Box::new(output.clone()), &header()).unwrap();
+        assert_eq!(
+            vec![concat!(
+                r#"{"command":"make all","env":{"TERM":"xterm"},"height":50,"#,
+                r#""timestamp":1234,"version":2,"width":100}"#
+            )],
+            output.lines()
+        );
+    }
+
+    #[test]
+    fn output_close_in_time_is_merged_into_one_event() {
+        let output = SharedVec::default();
+        let mut writer = CastWriter::new(Box::new(output.clone()), &header()).unwrap();
+        writer.output(ms(1000), b"a").unwrap();
+        writer.output(ms(1000), b"b").unwrap();
+        writer.output(ms(1500), b"\x1b[2K\"c\"\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(
+            vec![
+                r#"[1.000000, "o", "ab"]"#,
+                r#"[1.500000, "o", "\u001b[2K\"c\"\n"]"#
+            ],
+            output.lines()[1..]
+        );
+    }
+
+    #[test]
+    fn utf8_characters_are_not_split_between_events() {
+        let output = SharedVec::default();
+        let mut writer = CastWriter::new(Box::new(output.clone()), &header()).unwrap();
+        let heart = "💖".as_bytes();
+        writer.output(ms(1000), b"a").unwrap();
+        writer.output(ms(1000), &heart[..2]).unwrap();
+        writer.output(ms(2000), &heart[2..]).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(
+            vec![r#"[1.000000, "o", "a"]"#, r#"[2.000000, "o", "💖"]"#],
+            output.lines()[1..]
+        );
+    }
+
+    #[test]
+    fn exit_is_written_after_output() {
+        let output = SharedVec::default();
+        let mut writer = CastWriter::new(Box::new(output.clone()), &header()).unwrap();
+        writer.output(ms(1000), b"a").unwrap();
+        writer.exit(ms(3000), 17).unwrap();
+
+        assert_eq!(
+            vec![r#"[1.000000, "o", "a"]"#, r#"[3.000000, "x", "17"]"#],
+            output.lines()[1..]
+        );
+    }
+
+    #[test]
+    fn written_cast_can_be_read() {
+        let output = SharedVec::default();
+        let mut writer = CastWriter::new(Box::new(output.clone()), &header()).unwrap();
+        writer.output(ms(1000), b"a\x1b[2K").unwrap();
+        writer.output(ms(1500), b"\"b\"\n").unwrap();
+        writer.exit(ms(3000), 17).unwrap();
+        let text = output.lines().join("\n");
+
+        let event = |time, data: &str| Event {
+            time,
+            stream: String::new(),
+            data: data.to_string(),
+        };
+        assert_eq!(
+            Recording {
+                events: vec![event(ms(1000), "a\x1b[2K"), event(ms(1500), "\"b\"\n")],
+                exit: Some((ms(3000), 17)),
+                end: None,
+            },
+            read(text.as_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn other_events_are_ignored() {
+        let text = "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0.5, \"i\", \"x\"]\n";
+        assert_eq!(Recording::default(), read(text.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn invalid_cast_is_rejected() {
+        assert_eq!(
+            "Line 1: Not an asciinema v2 cast header",
+            read("{\"version\": 1}".as_bytes()).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "Line 2: Expected event as [time, code, data]",
+            read("{\"version\": 2}\n[\"o\", 1.0]".as_bytes())
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
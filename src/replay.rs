@@ -0,0 +1,248 @@
+use crate::output::timestamp::Timestamp;
+use crate::record::Event;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A Read giving recorded output of one stream. The time of the common Timestamp is set to the
+/// time the output was recorded, to get timestamps as when recorded instead of when replayed.
+/// Readers of different streams take turns, to replay events in the recorded order.
+pub struct ReplayReader {
+    // Events of the stream, with their position among the events of all streams
+    events: VecDeque<(usize, Event)>,
+    // Output of current event and position of the next byte to read
+    data: Vec<u8>,
+    position: usize,
+    // Position of the current event, which has the turn until the next read after its output
+    current: Option<usize>,
+    turns: Arc<Turns>,
+    timestamp: Arc<Mutex<Timestamp>>,
+    // Start of replay and speed factor when replaying with timing, otherwise output is replayed
+    // as fast as possible
+    pace: Option<(Instant, f64)>,
+}
+
+/// Position of the event to replay next, shared by the readers of all streams
+struct Turns {
+    next: Mutex<usize>,
+    changed: Condvar,
+    count: usize,
+    // Time set when all events are read
+    end_time: Duration,
+}
+
+impl ReplayReader {
+    /// Creates a reader for each stream of the events, with the name of the stream, in the order
+    /// the streams first give output
+    pub fn streams(
+        events: Vec<Event>,
+        end_time: Duration,
+        timestamp: Arc<Mutex<Timestamp>>,
+        speed: Option<f64>,
+    ) -> Vec<(String, Self)> {
+        let turns = Arc::new(Turns {
+            next: Mutex::new(0),
+            changed: Condvar::new(),
+            count: events.len(),
+            end_time,
+        });
+        if events.is_empty() {
+            set_time(&timestamp, end_time);
+        }
+        let pace = speed.map(|speed| (Instant::now(), speed));
+        let mut readers = Vec::<(String, Self)>::new();
+        for (index, event) in events.into_iter().enumerate() {
+            let position = readers.iter().position(|(name, _)| *name == event.stream);
+            let reader = match position {
+                Some(position) => &mut readers[position].1,
+                None => {
+                    let reader = Self {
+                        events: VecDeque::new(),
+                        data: vec![],
+                        position: 0,
+                        current: None,
+                        turns: turns.clone(),
+                        timestamp: timestamp.clone(),
+                        pace,
+                    };
+                    readers.push((event.stream.clone(), reader));
+                    &mut readers.last_mut().expect("Reader was just added").1
+                }
+            };
+            reader.events.push_back((index, event));
+        }
+        readers
+    }
+
+    /// Gives the turn to the next event, and moves to the next event of the stream, waiting until
+    /// it is its turn and it should be replayed. Returns false if there are no more events.
+    fn next_event(&mut self) -> bool {
+        if let Some(current) = self.current.take() {
+            self.turns.pass(current, &self.timestamp);
+        }
+        let Some((index, event)) = self.events.pop_front() else {
+            return false;
+        };
+        self.turns.wait_for(index);
+        self.wait_until(event.time);
+        set_time(&self.timestamp, event.time);
+        self.current = Some(index);
+        self.data = event.data.into_bytes();
+        self.position = 0;
+        true
+    }
+
+    fn wait_until(&self, time: Duration) {
+        if let Some((start, speed)) = self.pace {
+            let replay_time = start + time.div_f64(speed);
+            let now = Instant::now();
+            if replay_time > now {
+                std::thread::sleep(replay_time - now);
+            }
+        }
+    }
+}
+
+impl Turns {
+    fn wait_for(&self, index: usize) {
+        // If another reader panicked, events are replayed without waiting for turns
+        let Ok(mut next) = self.next.lock() else {
+            return;
+        };
+        while *next < index {
+            match self.changed.wait(next) {
+                Ok(guard) => next = guard,
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Passes the turn from an event to the next, the end time is set after the last event
+    fn pass(&self, index: usize, timestamp: &Arc<Mutex<Timestamp>>) {
+        if index + 1 == self.count {
+            set_time(timestamp, self.end_time);
+        }
+        if let Ok(mut next) = self.next.lock() {
+            *next = index + 1;
+        }
+        self.changed.notify_all();
+    }
+}
+
+fn set_time(timestamp: &Arc<Mutex<Timestamp>>, time: Duration) {
+    if let Ok(mut timestamp) = timestamp.lock() {
+        timestamp.set(time);
+    }
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position >= self.data.len() {
+            if !self.next_event() {
+                return Ok(0);
+            }
+        }
+        let length = buf.len().min(self.data.len() - self.position);
+        buf[..length].copy_from_slice(&self.data[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(millis: u64, stream: &str, data: &str) -> Event {
+        Event {
+            time: Duration::from_millis(millis),
+            stream: stream.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn output_is_read_with_time_set_to_recorded_time() {
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        let mut streams = ReplayReader::streams(
+            vec![
+                event(1000, "", "ab"),
+                event(2000, "", ""),
+                event(3000, "", "c"),
+            ],
+            Duration::from_millis(4000),
+            timestamp.clone(),
+            None,
+        );
+        assert_eq!(1, streams.len());
+        let reader = &mut streams[0].1;
+        let now = || timestamp.lock().unwrap().now();
+
+        let mut buffer = [0; 1];
+        assert_eq!(1, reader.read(&mut buffer).unwrap());
+        assert_eq!(b"a", &buffer);
+        assert_eq!(Duration::from_millis(1000), now());
+        assert_eq!(1, reader.read(&mut buffer).unwrap());
+        assert_eq!(b"b", &buffer);
+        assert_eq!(1, reader.read(&mut buffer).unwrap());
+        assert_eq!(b"c", &buffer);
+        assert_eq!(Duration::from_millis(3000), now());
+        assert_eq!(0, reader.read(&mut buffer).unwrap());
+        assert_eq!(Duration::from_millis(4000), now());
+    }
+
+    #[test]
+    fn streams_take_turns_in_recorded_order() {
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        let streams = ReplayReader::streams(
+            vec![
+                event(1000, "stdout", "a"),
+                event(2000, "stderr", "b"),
+                event(3000, "stdout", "c"),
+            ],
+            Duration::from_millis(4000),
+            timestamp.clone(),
+            None,
+        );
+        let names = streams
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["stdout", "stderr"], names);
+        let mut readers = streams.into_iter().map(|(_, reader)| reader);
+        let (mut stdout, mut stderr) = (readers.next().unwrap(), readers.next().unwrap());
+
+        let read = std::thread::spawn(move || {
+            let mut output = String::new();
+            stderr.read_to_string(&mut output).unwrap();
+            output
+        });
+        let mut buffer = [0; 1];
+        assert_eq!(1, stdout.read(&mut buffer).unwrap());
+        assert_eq!(b"a", &buffer);
+        // The event of stderr is replayed after the first event of stdout has been read
+        assert_eq!(1, stdout.read(&mut buffer).unwrap());
+        assert_eq!(b"c", &buffer);
+        assert_eq!(Duration::from_millis(3000), timestamp.lock().unwrap().now());
+        assert_eq!(0, stdout.read(&mut buffer).unwrap());
+        assert_eq!("b", read.join().unwrap());
+        assert_eq!(Duration::from_millis(4000), timestamp.lock().unwrap().now());
+    }
+
+    #[test]
+    fn output_is_paced_when_replaying_with_speed() {
+        let timestamp = Arc::new(Mutex::new(Timestamp::new()));
+        let mut streams = ReplayReader::streams(
+            vec![event(100, "", "a")],
+            Duration::from_millis(100),
+            timestamp,
+            Some(2.0),
+        );
+
+        let start = Instant::now();
+        let mut buffer = [0; 1];
+        assert_eq!(1, streams[0].1.read(&mut buffer).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
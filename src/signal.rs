@@ -0,0 +1,110 @@
+#[cfg(unix)]
+use crate::error::ErrorWithContext;
+use crate::error::Result;
+
+/// Calls a function from another thread each time one of the signals is received. Once means
+/// that a signal is only handled the first time, later signals are handled as if no handler was
+/// installed.
+#[cfg(unix)]
+pub fn on_signals(
+    signals: &[libc::c_int],
+    once: bool,
+    mut handle: impl FnMut() -> bool + Send + 'static,
+) -> Result<()> {
+    use std::io::Read;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::sync::atomic::Ordering;
+
+    let error =
+        || ErrorWithContext::wrap("Failed to handle signals", std::io::Error::last_os_error());
+    let mut fds = [0; 2];
+    // SAFETY: pipe() only writes the two descriptors to the array
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(error());
+    }
+    // SAFETY: fcntl() only changes the flags of the descriptors. Writing never blocks the signal
+    // handler, signals are only lost if the pipe is full.
+    unsafe {
+        libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK);
+    }
+    // SAFETY: The read end is open and owned by nothing else
+    let mut received = std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fds[0]) });
+    for &signal in signals {
+        let Some(slot) = usize::try_from(signal).ok().and_then(|n| PIPES.get(n)) else {
+            continue;
+        };
+        slot.store(fds[1], Ordering::SeqCst);
+        ONCE[signal as usize].store(once, Ordering::SeqCst);
+        // SAFETY: The handler only uses async-signal-safe functions and atomics
+        unsafe {
+            libc::signal(signal, notify as *const () as libc::sighandler_t);
+        }
+    }
+    std::thread::spawn(move || {
+        let mut buf = [0; 16];
+        while let Ok(length) = received.read(&mut buf) {
+            for _ in 0..length {
+                if !handle() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Calls a function when linetime is interrupted or terminated, e.g., to stop reading input that
+/// otherwise doesn't end. A second signal ends linetime as if no handler was installed.
+pub fn on_interrupt(stop: impl FnOnce() + Send + 'static) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut stop = Some(stop);
+        on_signals(
+            &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP],
+            true,
+            move || {
+                if let Some(stop) = stop.take() {
+                    stop();
+                }
+                true
+            },
+        )
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = stop;
+        Ok(())
+    }
+}
+
+/// Number of signals with a slot for the pipe their handler writes to
+#[cfg(unix)]
+const SIGNAL_COUNT: usize = 65;
+
+/// Write ends of the pipes the signal handler writes to, by signal number, -1 if not handled
+#[cfg(unix)]
+static PIPES: [std::sync::atomic::AtomicI32; SIGNAL_COUNT] =
+    [const { std::sync::atomic::AtomicI32::new(-1) }; SIGNAL_COUNT];
+
+/// Set for signals only handled the first time, by signal number
+#[cfg(unix)]
+static ONCE: [std::sync::atomic::AtomicBool; SIGNAL_COUNT] =
+    [const { std::sync::atomic::AtomicBool::new(false) }; SIGNAL_COUNT];
+
+#[cfg(unix)]
+extern "C" fn notify(signal: libc::c_int) {
+    use std::sync::atomic::Ordering;
+
+    let Some(fd) = PIPES.get(signal as usize) else {
+        return;
+    };
+    // SAFETY: signal() and write() are async-signal-safe
+    unsafe {
+        if ONCE[signal as usize].load(Ordering::SeqCst) {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+        libc::write(fd.load(Ordering::SeqCst), [1u8].as_ptr().cast(), 1);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::error::{ErrorWithContext, MessageError, Result, ResultExt};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Size of the chunks read by a TeeReader, to copy more than a byte at a time
+const CHUNK_SIZE: usize = 4096;
+
+/// A Write that can be shared between threads, e.g., a log file getting copies of several streams.
+/// Each write is made while holding a lock.
+#[derive(Clone)]
+pub struct SharedWriter(Arc<Mutex<dyn Write + Send>>);
+
+impl SharedWriter {
+    pub fn new(inner: impl Write + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(inner)))
+    }
+
+    /// Opens a file to write to, the file is truncated if it exists
+    pub fn create(path: &str) -> Result<Self> {
+        let file =
+            std::fs::File::create(path).error_context(format!("Failed to create file '{path}'"))?;
+        Ok(Self::new(file))
+    }
+
+    /// Opens a file to write to with buffering, written when flushed
+    pub fn create_buffered(path: &str) -> Result<Self> {
+        let file =
+            std::fs::File::create(path).error_context(format!("Failed to create file '{path}'"))?;
+        Ok(Self::new(std::io::BufWriter::new(file)))
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // If another thread panicked while writing, we proceed anyway
+        let mut inner = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut inner = self.0.lock().unwrap_or_else(|error| error.into_inner());
+        inner.flush()
+    }
+}
+
+/// A Read decorator that copies all bytes read from the inner Read to other writers. The inner
+/// Read is read in chunks, each copied at once, and the copies are flushed at end of file. A
+/// UTF-8 character split between chunks is copied with the next chunk, to not be split by
+/// another stream copied to the same writer.
+pub struct TeeReader<R> {
+    inner: R,
+    copies: Vec<SharedWriter>,
+    // Chunk read from the inner Read, with the position of the bytes not yet read from this
+    chunk: Vec<u8>,
+    position: usize,
+    // Start of a UTF-8 character at the end of the last chunk, not yet copied
+    partial: Vec<u8>,
+}
+
+impl<R: Read> TeeReader<R> {
+    pub fn new(inner: R, copies: Vec<SharedWriter>) -> Self {
+        Self {
+            inner,
+            copies,
+            chunk: vec![],
+            position: 0,
+            partial: vec![],
+        }
+    }
+
+    /// Copies the chunk read, except the start of a character at the end unless at end of file
+    fn copy_chunk(&mut self) -> std::io::Result<()> {
+        if self.copies.is_empty() {
+            return Ok(());
+        }
+        let mut bytes = std::mem::take(&mut self.partial);
+        bytes.extend_from_slice(&self.chunk);
+        let end_of_file = self.chunk.is_empty();
+        if !end_of_file {
+            let complete_length = match std::str::from_utf8(&bytes) {
+                Err(error) if error.error_len().is_none() => error.valid_up_to(),
+                _ => bytes.len(),
+            };
+            self.partial = bytes.split_off(complete_length);
+        }
+        for copy in &mut self.copies {
+            copy.write_all(&bytes)?;
+            if end_of_file {
+                copy.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.chunk.len() {
+            self.chunk.resize(CHUNK_SIZE, 0);
+            let length = self.inner.read(&mut self.chunk);
+            self.chunk.truncate(*length.as_ref().unwrap_or(&0));
+            self.position = 0;
+            length?;
+            self.copy_chunk()?;
+        }
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// A Write decorator that writes to the inner Write and copies everything to other writers
+pub struct TeeWriter<W> {
+    inner: W,
+    copies: Vec<SharedWriter>,
+}
+
+impl<W: Write> TeeWriter<W> {
+    pub fn new(inner: W, copies: Vec<SharedWriter>) -> Self {
+        Self { inner, copies }
+    }
+}
+
+impl<W: Write> Write for TeeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write_all(buf)?;
+        for copy in &mut self.copies {
+            copy.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()?;
+        for copy in &mut self.copies {
+            copy.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Log files getting copies of the raw input and the timestamped output
+#[derive(Default)]
+pub struct LogFiles {
+    // Raw logs with the stream they are for, or None if for all streams
+    raw: Vec<(Option<String>, SharedWriter)>,
+    timed: Vec<SharedWriter>,
+}
+
+impl LogFiles {
+    /// Opens log files. A raw log is given either as `FILE`, to get all streams, or as
+    /// `STREAM=FILE`, to only get one of the streams. A raw log looking like `STREAM=FILE` for a
+    /// stream that isn't read is rejected, a file name with `=` can be given as `./NAME=FILE`.
+    /// Files are only opened once, even if used for several logs, and can't also be sinks.
+    pub fn open(
+        files: &mut OutputFiles,
+        raw_logs: &[String],
+        timed_logs: &[String],
+        streams: &[&str],
+    ) -> Result<Self> {
+        let mut open = |path| files.open(path, FileUse::Log);
+
+        let mut result = Self::default();
+        for raw_log in raw_logs {
+            let (stream, path) = match streams.iter().find_map(|stream| {
+                raw_log
+                    .strip_prefix(stream)
+                    .and_then(|rest| rest.strip_prefix('='))
+                    .map(|path| (stream, path))
+            }) {
+                Some((stream, path)) => (Some(stream.to_string()), path),
+                None => match raw_log.split_once('=') {
+                    Some((stream, _)) if !stream.starts_with(['.', '/']) => {
+                        return Err(ErrorWithContext::wrap(
+                            format!("Invalid raw log '{raw_log}'"),
+                            MessageError(format!(
+                                "There is no stream '{stream}', a file name with '=' can be \
+                                 given as './{raw_log}'"
+                            )),
+                        ));
+                    }
+                    _ => (None, raw_log.as_str()),
+                },
+            };
+            result.raw.push((stream, open(path)?));
+        }
+        for timed_log in timed_logs {
+            result.timed.push(open(timed_log)?);
+        }
+        Ok(result)
+    }
+
+    /// Gets the raw logs for a stream
+    pub fn raw(&self, stream: &str) -> Vec<SharedWriter> {
+        self.raw
+            .iter()
+            .filter(|(log_stream, _)| log_stream.as_ref().map_or(true, |s| s == stream))
+            .map(|(_, log)| log.clone())
+            .collect()
+    }
+
+    /// Gets the logs for timestamped output
+    pub fn timed(&self) -> Vec<SharedWriter> {
+        self.timed.clone()
+    }
+}
+
+/// Where the timestamped output of a stream is written
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sink {
+    Stdout,
+    Stderr,
+    File(String),
+}
+
+impl FromStr for Sink {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "" => Err(MessageError(
+                "Expected stdout, stderr or a file".to_string(),
+            )),
+            "stdout" => Ok(Self::Stdout),
+            "stderr" => Ok(Self::Stderr),
+            path => Ok(Self::File(path.to_string())),
+        }
+    }
+}
+
+/// A stream written to a sink other than the default, e.g., `build/err=stdout`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub stream: String,
+    pub sink: Sink,
+}
+
+impl FromStr for Route {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((stream, sink)) if !stream.is_empty() => Ok(Self {
+                stream: stream.to_string(),
+                sink: sink.parse()?,
+            }),
+            _ => Err(MessageError(format!(
+                "Invalid route '{s}', expected STREAM=SINK"
+            ))),
+        }
+    }
+}
+
+/// Opens sinks to write to, with a file only opened once even if several streams are written to
+/// it, but not if it is also a log. The file is truncated if it exists.
+pub fn open_sinks(files: &mut OutputFiles, sinks: &[Sink]) -> Result<Vec<Box<dyn Write + Send>>> {
+    sinks
+        .iter()
+        .map(|sink| -> Result<Box<dyn Write + Send>> {
+            Ok(match sink {
+                Sink::Stdout => Box::new(std::io::stdout()),
+                Sink::Stderr => Box::new(std::io::stderr()),
+                Sink::File(path) => Box::new(files.open(path, FileUse::Sink)?),
+            })
+        })
+        .collect()
+}
+
+/// What a file written to is used for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FileUse {
+    Log,
+    Sink,
+}
+
+/// Files written to, each opened once and shared by all writers using it for the same purpose.
+/// A file can't be both a log and a sink, as they would overwrite each other.
+#[derive(Default)]
+pub struct OutputFiles(HashMap<String, (FileUse, SharedWriter)>);
+
+impl OutputFiles {
+    fn open(&mut self, path: &str, file_use: FileUse) -> Result<SharedWriter> {
+        match self.0.get(path) {
+            Some((opened_use, file)) if *opened_use == file_use => Ok(file.clone()),
+            Some(_) => Err(ErrorWithContext::wrap(
+                format!("Invalid file '{path}'"),
+                MessageError("It can't be both a log and a stream output".to_string()),
+            )),
+            None => {
+                // Logs are buffered and flushed at the end of their streams, while sinks get
+                // lines written as they are printed
+                let file = match file_use {
+                    FileUse::Log => SharedWriter::create_buffered(path)?,
+                    FileUse::Sink => SharedWriter::create(path)?,
+                };
+                self.0.insert(path.to_string(), (file_use, file.clone()));
+                Ok(file)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{SharedVec, TempDir};
+
+    #[test]
+    fn tee_reader_copies_read_bytes() {
+        let copy = SharedVec::default();
+        let mut input = "hello".as_bytes();
+        let mut reader = TeeReader::new(&mut input, vec![SharedWriter::new(copy.clone())]);
+
+        let mut buffer = [0; 3];
+        assert_eq!(3, reader.read(&mut buffer).unwrap());
+        assert_eq!("hello", copy.text());
+        assert_eq!(2, reader.read(&mut buffer).unwrap());
+        assert_eq!(b"lo", &buffer[..2]);
+        assert_eq!(0, reader.read(&mut buffer).unwrap());
+        assert_eq!("hello", copy.text());
+    }
+
+    #[test]
+    fn tee_reader_copies_characters_split_between_chunks_whole() {
+        let copy = SharedVec::default();
+        let mut input = std::io::Cursor::new(b"a\xc3".to_vec()).chain(&b"\xa9b"[..]);
+        let mut reader = TeeReader::new(&mut input, vec![SharedWriter::new(copy.clone())]);
+
+        let mut buffer = [0; 8];
+        assert_eq!(2, reader.read(&mut buffer).unwrap());
+        assert_eq!("a", copy.text());
+        assert_eq!(2, reader.read(&mut buffer).unwrap());
+        assert_eq!("a\u{e9}b", copy.text());
+    }
+
+    #[test]
+    fn tee_writer_writes_to_inner_and_copies() {
+        let copy = SharedVec::default();
+        let mut inner = Vec::<u8>::new();
+        let mut writer = TeeWriter::new(&mut inner, vec![SharedWriter::new(copy.clone())]);
+
+        writer.write_all(b"hello").unwrap();
+        assert_eq!(b"hello", inner.as_slice());
+        assert_eq!("hello", copy.text());
+    }
+
+    #[test]
+    fn raw_logs_can_be_for_all_or_one_stream() {
+        let dir = TempDir::new("tee");
+        let all = dir.file("all.log");
+        let err = dir.file("err.log");
+
+        let logs = LogFiles::open(
+            &mut OutputFiles::default(),
+            &[all.clone(), format!("stderr={err}")],
+            &[],
+            &["stdout", "stderr"],
+        )
+        .unwrap();
+        logs.raw("stdout")[0].write_all(b"out").unwrap();
+        for mut log in logs.raw("stderr") {
+            log.write_all(b"err").unwrap();
+            log.flush().unwrap();
+        }
+
+        assert_eq!(1, logs.raw("stdout").len());
+        assert_eq!("outerr", std::fs::read_to_string(&all).unwrap());
+        assert_eq!("err", std::fs::read_to_string(&err).unwrap());
+    }
+
+    #[test]
+    fn raw_logs_for_streams_not_read_are_rejected() {
+        let dir = TempDir::new("tee-unknown");
+        let path = dir.file("err.log");
+
+        let raw_log = [format!("stderr={path}")];
+        let mut files = OutputFiles::default();
+        assert!(LogFiles::open(&mut files, &raw_log, &[], &[]).is_err());
+        assert!(LogFiles::open(&mut files, &raw_log, &[], &["stdin"]).is_err());
+        assert!(!std::path::Path::new(&path).exists());
+        // Absolute or relative paths with '=' are files
+        let named = dir.file("a=b.log");
+        LogFiles::open(&mut files, std::slice::from_ref(&named), &[], &["stdin"]).unwrap();
+        assert!(std::path::Path::new(&named).exists());
+    }
+
+    #[test]
+    fn routes_are_parsed() {
+        assert_eq!(
+            Ok(Route {
+                stream: "build/err".to_string(),
+                sink: Sink::Stdout
+            }),
+            "build/err=stdout".parse()
+        );
+        assert_eq!(
+            Ok(Route {
+                stream: "stderr".to_string(),
+                sink: Sink::File("logs/err.log".to_string())
+            }),
+            "stderr=logs/err.log".parse()
+        );
+        assert!("stderr".parse::<Route>().is_err());
+        assert!("=stdout".parse::<Route>().is_err());
+        assert!("stderr=".parse::<Route>().is_err());
+    }
+
+    #[test]
+    fn sinks_share_files() {
+        let dir = TempDir::new("sinks");
+        let path = dir.file("all.log");
+
+        let mut files = OutputFiles::default();
+        let sinks = [Sink::File(path.clone()), Sink::File(path.clone())];
+        let mut sinks = open_sinks(&mut files, &sinks).unwrap();
+        sinks[0].write_all(b"out ").unwrap();
+        sinks[1].write_all(b"err").unwrap();
+
+        assert_eq!("out err", std::fs::read_to_string(&path).unwrap());
+        // A sink can't also be a log
+        assert!(LogFiles::open(&mut files, &[], std::slice::from_ref(&path), &[]).is_err());
+    }
+}
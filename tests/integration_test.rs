@@ -2,11 +2,13 @@ mod assertions;
 mod marionette_control;
 mod paths;
 mod program_under_test;
+mod temp_dir;
 
 use assertions::{
     assert_command_output_end, assert_input_end, assert_near, assert_ok, assert_timeout,
 };
 use program_under_test::Linetime;
+use temp_dir::TempDir;
 
 use std::time::Duration;
 use tokio::time::timeout;
@@ -342,3 +344,1040 @@ async fn escape_sequence_to_move_cursor_is_swallowed_to_unfold_lines() {
 
     assert!(put.wait().await.success());
 }
+
+#[tokio::test]
+async fn prefix_template_changes_layout_of_lines_and_end_line() {
+    let mut args = to_os(vec!["--prefix-template", "{elapsed}\\t[{stream:>7}]\\t"]);
+    args.append(&mut marionette_control::app_path_and_args(vec![]));
+    let mut put = Linetime::run(args);
+    let mut control = marionette_control::Bar::new().await;
+
+    control.stdout("hello\n").await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout("\t[ stdout]\thello\n"));
+
+    control.exit(0).await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout("\t[ ------]\t⏱ End\n"));
+
+    assert!(put.wait().await.success());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn lines_of_several_streams_are_logged_whole_without_line_buffering() {
+    let dir = TempDir::new("logs-unbuffered");
+    let timed_log = dir.join("timed.log");
+    let mut put = Linetime::run(to_os(vec![
+        "-l",
+        "--stdout-to",
+        "/dev/null",
+        "--stderr-to",
+        "/dev/null",
+        "--timed-log",
+        &timed_log.to_string_lossy(),
+        "-s",
+        "for i in $(seq 200); do echo out$i; done & for i in $(seq 200); do echo err$i >&2; done; wait",
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+
+    let timed = std::fs::read_to_string(&timed_log).unwrap();
+    let lines = timed.lines().collect::<Vec<_>>();
+    assert_eq!(401, lines.len());
+    for line in &lines[..400] {
+        let (_, text) = line.split_once(' ').unwrap();
+        assert!(
+            text.starts_with("stdout: out") || text.starts_with("stderr: err"),
+            "{line}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn file_can_not_be_both_log_and_stream_output() {
+    let dir = TempDir::new("logs-sink");
+    let path = dir.join("out.log").to_string_lossy().into_owned();
+    let mut put = Linetime::run(to_os(vec![
+        "--stdout-to",
+        &path,
+        "--timed-log",
+        &path,
+        "true",
+    ]));
+
+    let message = format!("Invalid file '{path}': It can't be both a log and a stream output\n");
+    assert_ok!(put.read_stderr(&message));
+    assert_eq!(Some(1), put.wait().await.code());
+}
+
+#[tokio::test]
+async fn raw_input_and_timestamped_output_can_be_logged_to_files() {
+    let dir = TempDir::new("logs");
+    let raw_log = dir.join("raw.log");
+    let timed_log = dir.join("timed.log");
+    let mut put = Linetime::run(vec![
+        "--raw-log".into(),
+        raw_log.clone().into(),
+        "--timed-log".into(),
+        timed_log.clone().into(),
+    ]);
+
+    put.write_stdin("hello\x1b[2Kworld\n").await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": hello\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": world\n"));
+
+    put.close_stdin();
+    assert_input_end!(put);
+    assert!(put.wait().await.success());
+
+    assert_eq!(
+        "hello\x1b[2Kworld\n",
+        std::fs::read_to_string(&raw_log).unwrap()
+    );
+    let timed = std::fs::read_to_string(&timed_log).unwrap();
+    let lines = timed.lines().collect::<Vec<_>>();
+    assert_eq!(3, lines.len());
+    assert!(lines[0].ends_with(": hello"));
+    assert!(lines[1].ends_with(": world"));
+    assert!(lines[2].ends_with(": ⏱ End"));
+}
+
+#[tokio::test]
+async fn input_can_be_recorded_to_cast_file() {
+    let dir = TempDir::new("cast");
+    let cast = dir.join("recording.cast");
+    let mut args = vec!["--record".into(), cast.clone().into()];
+    args.append(&mut marionette_control::app_path_and_args(vec![]));
+    let mut put = Linetime::run(args);
+    let mut control = marionette_control::Bar::new().await;
+
+    control.stdout("hello\n").await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout: hello\n"));
+
+    control.exit(17).await;
+    assert_command_output_end!(&mut put);
+    assert_ok!(put.read_stderr("Command exited with 17\n"));
+    assert_eq!(Some(17), put.wait().await.code());
+
+    let recording = std::fs::read_to_string(&cast).unwrap();
+    let lines = recording
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(3, lines.len());
+    assert_eq!(2, lines[0]["version"]);
+    assert_eq!("o", lines[1][1]);
+    assert_eq!("hello\n", lines[1][2]);
+    assert_eq!("x", lines[2][1]);
+    assert_eq!("17", lines[2][2]);
+    assert!(lines[1][0].as_f64().unwrap() <= lines[2][0].as_f64().unwrap());
+}
+
+#[tokio::test]
+async fn recording_is_replayed_with_recorded_timestamps() {
+    let dir = TempDir::new("replay");
+    let cast = dir.join("recording.cast");
+    std::fs::write(
+        &cast,
+        concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[1.5, \"o\", \"hello\\n\"]\n",
+            "[2.25, \"o\", \"wor\"]\n",
+            "[3.0, \"o\", \"ld\\n\"]\n",
+            "[4.0, \"x\", \"3\"]\n"
+        ),
+    )
+    .unwrap();
+    let mut put = Linetime::run(vec!["replay".into(), "-d".into(), cast.into()]);
+
+    assert_ok!(put.read_stdout("00:01.500            : hello\n"));
+    assert_ok!(put.read_stdout("00:02.250 (00:00.750): world\n"));
+    assert_ok!(put.read_stdout("00:04.000 (00:01.750): ⏱ End\n"));
+    assert_ok!(put.read_stderr("Command exited with 3\n"));
+    assert_eq!(Some(3), put.wait().await.code());
+}
+
+#[tokio::test]
+async fn interleaved_streams_of_linetime_output_are_replayed() {
+    let dir = TempDir::new("replay");
+    let log = dir.join("run.log");
+    std::fs::write(
+        &log,
+        concat!(
+            "00:00.303 stderr: Step 1\n",
+            "00:00.001 stdout: Building\n",
+            "00:00.806 stdout: ding\n",
+            "00:01.000 ------: ⏱ End\n"
+        ),
+    )
+    .unwrap();
+    let mut put = Linetime::run(vec!["replay".into(), "-d".into(), log.into()]);
+
+    assert_ok!(put.read_stdout("00:00.303             stderr: Step 1\n"));
+    assert_ok!(put.read_stdout("00:00.303 (00:00.000) stdout: Building\n"));
+    assert_ok!(put.read_stdout("00:00.806 (00:00.503) stdout: ding\n"));
+    assert_ok!(put.read_stdout("00:01.000 (00:00.194) ------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn options_of_other_modes_are_rejected() {
+    for (args, option, reason) in [
+        (
+            vec!["--rusage"],
+            "--rusage",
+            "It can only be used when executing a command",
+        ),
+        (
+            vec!["--grace-period", "2"],
+            "--grace-period",
+            "It can only be used when executing a command",
+        ),
+        (
+            vec!["--exit-policy", "first"],
+            "--exit-policy",
+            "It can only be used when executing a command",
+        ),
+        (
+            vec!["--fd", "3=progress"],
+            "--fd",
+            "It can only be used when executing a command",
+        ),
+        (
+            vec!["--processes", "1"],
+            "--processes",
+            "It can only be used when executing a command",
+        ),
+        (
+            vec!["--stderr-to", "stdout"],
+            "--stderr-to",
+            "It can only be used when executing a command",
+        ),
+        (
+            vec!["-l", "--control", "lt.ctl"],
+            "--control",
+            "It can't be used with --no-line-buffering",
+        ),
+        (
+            vec!["--route", "stdout=/dev/null"],
+            "--route",
+            "It can only be used when executing a command",
+        ),
+        (
+            vec!["--context", "2"],
+            "--context",
+            "It can only be used with --include or --exclude",
+        ),
+        (
+            vec!["--speed", "2", "true"],
+            "--speed",
+            "It can only be used with replay",
+        ),
+        (
+            vec!["replay", "--listen", "lt.sock", "run.cast"],
+            "--listen",
+            "It can't be used with replay",
+        ),
+        (
+            vec!["replay", "--follow", "app.log", "run.cast"],
+            "--follow",
+            "It can't be used with replay",
+        ),
+        (
+            vec!["replay", "--record", "new.cast", "run.cast"],
+            "--record",
+            "It can't be used with replay",
+        ),
+        (
+            vec!["replay", "--raw-log", "raw.log", "run.cast"],
+            "--raw-log",
+            "It can't be used with replay",
+        ),
+        (
+            vec!["replay", "--status", "run.cast"],
+            "--status",
+            "It can't be used with replay",
+        ),
+        (
+            vec!["parse", "--record", "new.cast"],
+            "--record",
+            "It can't be used with parse",
+        ),
+        (
+            vec!["parse", "--status"],
+            "--status",
+            "It can't be used with parse",
+        ),
+        (
+            vec!["parse", "-r"],
+            "--real-time",
+            "It can only be used with replay",
+        ),
+        (
+            vec!["--format", "csv"],
+            "--format",
+            "It can only be used with parse",
+        ),
+        (
+            vec!["replay", "--threshold", "1", "run.cast"],
+            "--threshold",
+            "It can only be used with diff",
+        ),
+        (
+            vec!["diff", "--phase", "^Test", "a.log", "b.log"],
+            "--phase",
+            "It can't be used with diff",
+        ),
+        (
+            vec!["diff", "--follow", "app.log", "a.log", "b.log"],
+            "--follow",
+            "It can't be used with diff",
+        ),
+        (
+            vec!["diff", "--raw-log", "raw.log", "a.log", "b.log"],
+            "--raw-log",
+            "It can't be used with diff",
+        ),
+        (
+            vec!["diff", "--trace-out", "t.json", "a.log", "b.log"],
+            "--trace-out",
+            "It can't be used with diff",
+        ),
+    ] {
+        let mut put = Linetime::run(args.into_iter().map(Into::into).collect());
+
+        let message = format!("Invalid option {option}: {reason}\n");
+        assert_ok!(put.read_stderr(&message));
+        assert_eq!(Some(1), put.wait().await.code());
+    }
+}
+
+#[tokio::test]
+async fn linetime_output_is_parsed_to_csv() {
+    let mut put = Linetime::run(vec!["parse".into(), "--format".into(), "csv".into()]);
+    put.write_stdin(concat!(
+        "00:01.500             stdout: hello, world\n",
+        "01:00:02.250 (01:00:00.750) stderr: error\n",
+        "01:00:04.000 (00:01.750) ------: ⏱ End\n"
+    ))
+    .await;
+    put.close_stdin();
+
+    assert_ok!(put.read_stdout("elapsed,delta,next,lineno,stream,stream_lineno,text,end\n"));
+    assert_ok!(put.read_stdout("1.500000,,,,stdout,,\"hello, world\",false\n"));
+    assert_ok!(put.read_stdout("3602.250000,3600.750000,,,stderr,,error,false\n"));
+    assert_ok!(put.read_stdout("3604.000000,1.750000,,,,,,true\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn parsed_lines_are_summarized_on_stderr() {
+    let mut put = Linetime::run(vec![
+        "parse".into(),
+        "--phase".into(),
+        "test=^Running".into(),
+    ]);
+    put.write_stdin(concat!(
+        "00:01.000 stdout: Compiling\n",
+        "00:02.000 (00:01.000) stdout: Running tests\n",
+        "00:04.000 (00:02.000) ------: ⏱ End\n"
+    ))
+    .await;
+    put.close_stdin();
+
+    for _ in 0..3 {
+        assert!(assert_ok!(put.read_stdout_line()).starts_with("{\"delta\":"));
+    }
+    assert_ok!(put.read_stderr("Phase   Duration   Share\ntest   00:02.000   50.0%\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn lines_and_phases_are_written_to_trace_file_and_summarized() {
+    let dir = TempDir::new("trace");
+    let trace = dir.join("trace.json");
+    let mut put = Linetime::run(vec![
+        "--trace-out".into(),
+        trace.clone().into(),
+        "--phase".into(),
+        "build=^Building".into(),
+    ]);
+
+    put.write_stdin("Building\nhello\n").await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": Building\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": \u{25b6} build\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": hello\n"));
+    put.close_stdin();
+    assert_input_end!(put);
+    assert_ok!(put.read_stdout("Phase   Duration   Share\nbuild  "));
+    assert_ok!(put.read_stdout_timestamp());
+    assert!(assert_ok!(put.read_stdout_line()).ends_with("%\n"));
+    assert!(put.wait().await.success());
+
+    let trace: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&trace).unwrap()).unwrap();
+    let events = trace["traceEvents"].as_array().unwrap();
+    let complete = |name: &str| {
+        events
+            .iter()
+            .find(|event| event["ph"] == "X" && event["name"] == name)
+            .unwrap_or_else(|| panic!("No event for '{name}'"))
+    };
+    assert_eq!(0, complete("build")["tid"]);
+    assert_eq!(1, complete("Building")["tid"]);
+    assert_eq!(1, complete("hello")["tid"]);
+    assert_eq!(complete("Building")["ts"], complete("build")["ts"]);
+}
+
+#[tokio::test]
+async fn cargo_profile_ranks_crates_with_unfolded_progress_bar() {
+    let mut put = Linetime::run(vec!["--profile".into(), "cargo".into()]);
+    put.write_stdin(concat!(
+        "   Compiling memchr v2.7.4\n",
+        "    Building [=>   ] 0/2: memchr\r\x1b[K",
+        "   Compiling regex v1.12.3\n",
+        "    Building [===> ] 1/2: regex\r\x1b[K",
+        "    Finished `dev` profile [unoptimized + debuginfo] target(s) in 0.01s\n"
+    ))
+    .await;
+    put.close_stdin();
+
+    let mut lines = vec![];
+    while lines
+        .last()
+        .map_or(true, |line: &String| !line.starts_with("Crate"))
+    {
+        lines.push(assert_ok!(put.read_stdout_line()));
+    }
+    assert!(lines[lines.len() - 2].ends_with(": ⏱ End\n"));
+    let mut crates = [
+        assert_ok!(put.read_stdout_line()),
+        assert_ok!(put.read_stdout_line()),
+    ];
+    crates.sort();
+    assert!(crates[0].starts_with("memchr v2.7.4  "));
+    assert!(crates[1].starts_with("regex v1.12.3  "));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn runs_are_compared_and_regression_limit_gives_exit_code() {
+    let dir = TempDir::new("diff");
+    let old = dir.join("old.log");
+    let new = dir.join("new.cast");
+    std::fs::write(
+        &old,
+        concat!(
+            "00:01.000: Build 17\n",
+            "00:03.000: Test\n",
+            "00:03.500: Deploy\n"
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        &new,
+        concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[1.0, \"o\", \"Build 18\\n\"]\n",
+            "[5.0, \"o\", \"Test\\n\"]\n",
+            "[5.2, \"o\", \"Deploy\\n\"]\n"
+        ),
+    )
+    .unwrap();
+    let mut put = Linetime::run(vec![
+        "diff".into(),
+        "--mask".into(),
+        "[0-9]+".into(),
+        "--max-regression".into(),
+        "1.5".into(),
+        old.into(),
+        new.into(),
+    ]);
+
+    assert_ok!(put.read_stdout("    Change         Old         New  Line\n"));
+    assert_ok!(put.read_stdout("+00:02.000   00:02.000   00:04.000  Test\n"));
+    assert_ok!(put.read_stdout("-00:00.300   00:00.500   00:00.200  Deploy\n"));
+    assert_ok!(put.read_stderr("Regression limit exceeded\n"));
+    assert_eq!(Some(2), put.wait().await.code());
+}
+
+#[tokio::test]
+async fn runs_with_interleaved_streams_are_compared() {
+    let dir = TempDir::new("diff");
+    let old = dir.join("old.log");
+    let new = dir.join("new.log");
+    std::fs::write(
+        &old,
+        concat!("00:01.000 stdout: Build\n", "00:02.000 stdout: Test\n"),
+    )
+    .unwrap();
+    std::fs::write(
+        &new,
+        concat!(
+            "00:01.500 stderr: Build\n",
+            "00:01.000 stdout: Step\n",
+            "00:04.500 stdout: Test\n"
+        ),
+    )
+    .unwrap();
+    let mut put = Linetime::run(vec!["diff".into(), old.into(), new.into()]);
+
+    assert_ok!(put.read_stdout("    Change         Old         New  Line\n"));
+    assert_ok!(put.read_stdout("+00:02.000   00:01.000   00:03.000  Test\n"));
+    assert_ok!(put.read_stdout("+00:00.500   00:01.000   00:01.500  Build\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn runs_with_cast_times_going_backwards_are_compared() {
+    let dir = TempDir::new("diff");
+    let old = dir.join("old.cast");
+    let new = dir.join("new.cast");
+    std::fs::write(
+        &old,
+        concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[1.0, \"o\", \"Build\\n\"]\n",
+            "[2.0, \"o\", \"Test\\n\"]\n"
+        ),
+    )
+    .unwrap();
+    std::fs::write(
+        &new,
+        concat!(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n",
+            "[2.0, \"o\", \"Build\\n\"]\n",
+            "[1.0, \"o\", \"Test\\n\"]\n"
+        ),
+    )
+    .unwrap();
+    let mut put = Linetime::run(vec!["diff".into(), old.into(), new.into()]);
+
+    assert_ok!(put.read_stdout("    Change         Old         New  Line\n"));
+    assert_ok!(put.read_stdout("+00:01.000   00:01.000   00:02.000  Build\n"));
+    assert_ok!(put.read_stdout("-00:01.000   00:01.000   00:00.000  Test\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn lines_are_held_to_show_time_until_next_line_when_looking_ahead() {
+    let mut args = to_os(vec!["--lookahead"]);
+    args.extend(marionette_control::app_path_and_args(vec![]));
+    let mut put = Linetime::run(args);
+    let mut control = marionette_control::Bar::new().await;
+
+    control.stdout("hello\n").await;
+    assert_timeout!(put.read_stdout_timestamp());
+    control.stdout("world\n").await;
+    assert_ok!(put.read_stdout_timestamp());
+    let line = assert_ok!(put.read_stdout_line());
+    assert!(line.starts_with(" [00:0"));
+    assert!(line.ends_with("] stdout: hello\n"));
+    let next_ms = line[5..7].parse::<u64>().unwrap() * 1000 + line[8..11].parse::<u64>().unwrap();
+    assert!(next_ms >= 500);
+
+    control.exit(0).await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert!(assert_ok!(put.read_stdout_line()).ends_with("] stdout: world\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout("             ------: ⏱ End\n"));
+
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn phase_banners_are_only_shown_with_their_shown_lines() {
+    for lookahead in [false, true] {
+        let mut args = to_os(vec!["--phase", "^Build", "--include", "^(x|Build b)"]);
+        if lookahead {
+            args.push("--lookahead".into());
+        }
+        let mut put = Linetime::run(args);
+
+        put.write_stdin("Build a\nnoise\nBuild b\nx\n").await;
+        put.close_stdin();
+        let mut lines = vec![];
+        for _ in 0..4 {
+            assert_ok!(put.read_stdout_timestamp());
+            lines.push(assert_ok!(put.read_stdout_line()));
+        }
+        let texts = lines
+            .iter()
+            .map(|line| line.split_once(": ").unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "Build b\n",
+                "▶ Build b\n",
+                "x\n",
+                "⏱ End (2 lines suppressed)\n"
+            ],
+            texts
+        );
+        // The phase table
+        for _ in 0..3 {
+            assert_ok!(put.read_stdout_line());
+        }
+        assert!(put.wait().await.success());
+    }
+}
+
+#[tokio::test]
+async fn lines_are_filtered_and_suppressed_lines_are_counted() {
+    let mut args = to_os(vec!["--include", "error|warning", "--exclude", "^note"]);
+    args.extend(marionette_control::app_path_and_args(vec![]));
+    let mut put = Linetime::run(args);
+    let mut control = marionette_control::Bar::new().await;
+
+    control.stdout("compiling\n").await;
+    control.stderr("an error\n").await;
+    assert_ok!(put.read_stderr_timestamp());
+    assert_ok!(put.read_stderr(" stderr: an error\n"));
+    control.stdout("note: an error\n").await;
+    control.stdout("a warning\n").await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout: a warning\n"));
+
+    control.exit(0).await;
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ End (2 lines suppressed)\n"));
+
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn options_are_read_from_config_file_profile_and_environment() {
+    let dir = TempDir::new("config");
+    std::fs::create_dir_all(dir.join("linetime")).unwrap();
+    std::fs::write(
+        dir.join("linetime").join("config.toml"),
+        concat!(
+            "prefix-template = \"{elapsed} \"\n",
+            "\n",
+            "[profile.quiet]\n",
+            "exclude = \"^debug\"\n"
+        ),
+    )
+    .unwrap();
+    let mut put = Linetime::run_with_env(
+        to_os(vec![
+            "--profile-name",
+            "quiet",
+            "--prefix-template",
+            "{lineno}: ",
+        ]),
+        vec![
+            ("XDG_CONFIG_HOME".into(), dir.to_path_buf().into()),
+            ("LINETIME_OPTS".into(), "--exclude '^trace'".into()),
+        ],
+    );
+
+    put.write_stdin("debug a\ntrace b\nhello\n").await;
+    put.close_stdin();
+    assert_ok!(put.read_stdout("3: hello\n"));
+    assert_ok!(put.read_stdout("-: ⏱ End (2 lines suppressed)\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn command_line_options_replace_and_unset_config_options() {
+    let dir = TempDir::new("config-override");
+    std::fs::create_dir_all(dir.join("linetime")).unwrap();
+    std::fs::write(
+        dir.join("linetime").join("config.toml"),
+        concat!(
+            "line-numbers = true\n",
+            "exclude = \"^debug\"\n",
+            "prefix-template = \"{lineno}: \"\n"
+        ),
+    )
+    .unwrap();
+    let mut put = Linetime::run_with_env(
+        to_os(vec![
+            "--exclude",
+            "^trace",
+            "--unset",
+            "line-numbers",
+            "--unset",
+            "prefix-template",
+        ]),
+        vec![("XDG_CONFIG_HOME".into(), dir.to_path_buf().into())],
+    );
+
+    put.write_stdin("debug a\ntrace b\nhello\n").await;
+    put.close_stdin();
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": debug a\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": hello\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(": ⏱ End (1 line suppressed)\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn control_is_rejected_with_no_line_buffering_from_config() {
+    let dir = TempDir::new("config-control");
+    std::fs::create_dir_all(dir.join("linetime")).unwrap();
+    std::fs::write(
+        dir.join("linetime").join("config.toml"),
+        "no-line-buffering = true\n",
+    )
+    .unwrap();
+    let mut put = Linetime::run_with_env(
+        to_os(vec!["--control", "lt.ctl"]),
+        vec![("XDG_CONFIG_HOME".into(), dir.to_path_buf().into())],
+    );
+
+    assert_ok!(
+        put.read_stderr("Invalid option --control: It can't be used with --no-line-buffering\n")
+    );
+    assert_eq!(Some(1), put.wait().await.code());
+}
+
+#[tokio::test]
+async fn command_line_command_replaces_config_shell_command() {
+    let dir = TempDir::new("config-command");
+    std::fs::create_dir_all(dir.join("linetime")).unwrap();
+    std::fs::write(
+        dir.join("linetime").join("config.toml"),
+        "shell = \"echo from config\"\n",
+    )
+    .unwrap();
+    let mut put = Linetime::run_with_env(
+        to_os(vec!["sh", "-c", "echo from arguments"]),
+        vec![("XDG_CONFIG_HOME".into(), dir.to_path_buf().into())],
+    );
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout: from arguments\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn several_commands_are_run_with_labeled_streams() {
+    let mut put = Linetime::run(to_os(vec![
+        "--cmd",
+        "ok=sh -c 'echo hello' ;; fail=sh -c 'sleep 0.5; echo world >&2; exit 3'",
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ok/out  : hello\n"));
+    assert_ok!(put.read_stderr_timestamp());
+    assert_ok!(put.read_stderr(" fail/err: world\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" --------: ⏱ End\n"));
+    assert_ok!(put.read_stderr("Command 'fail' exited with 3\n"));
+    assert_eq!(Some(3), put.wait().await.code());
+}
+
+#[tokio::test]
+async fn lines_of_streams_with_names_of_different_lengths_are_aligned() {
+    let mut put = Linetime::run(to_os(vec![
+        "--cmd",
+        "a=echo hi",
+        "--cmd",
+        "longname=sh -c 'sleep 0.2; echo there'",
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" a/out       : hi\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" longname/out: there\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn shell_command_line_is_executed() {
+    let mut put = Linetime::run(to_os(vec![
+        "-s",
+        "echo 'hello world' | tr a-z A-Z && exit 3",
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout: HELLO WORLD\n"));
+    assert_command_output_end!(put);
+    assert_ok!(put.read_stderr("Command exited with 3\n"));
+    assert_eq!(Some(3), put.wait().await.code());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn resource_usage_is_sampled_and_shown_at_end() {
+    let mut put = Linetime::run(to_os(vec![
+        "--rusage",
+        "--sample",
+        "0.2",
+        "-s",
+        "sleep 0.5; echo done",
+    ]));
+
+    for _ in 0..2 {
+        assert_ok!(put.read_stdout_timestamp());
+        assert_ok!(put.read_stdout(" sample: CPU "));
+        let sample = put.read_stdout_line().await.unwrap();
+        assert!(sample.contains("%, RSS "), "{sample}");
+    }
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout: done\n"));
+    assert_command_output_end!(put);
+    for name in [
+        "User CPU          ",
+        "System CPU        ",
+        "Max RSS           ",
+        "Page faults       ",
+        "Context switches  ",
+    ] {
+        assert_ok!(put.read_stdout(name));
+        assert_ok!(put.read_stdout_line());
+    }
+    assert_eq!(Some(0), put.wait().await.code());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn subprocesses_are_shown_when_started_and_exited() {
+    let mut put = Linetime::run(to_os(vec![
+        "--processes",
+        "0.2",
+        "-s",
+        "sleep 0.05; sleep 0.4; echo done",
+    ]));
+
+    let mut lines = vec![];
+    for _ in 0..3 {
+        assert_ok!(put.read_stdout_timestamp());
+        lines.push(assert_ok!(put.read_stdout_line()));
+    }
+    // The output of the command and the exit of the subprocess are in any order
+    lines.sort();
+    assert!(lines[0].starts_with(" processes: ⚙ "), "{lines:?}");
+    assert!(lines[0].contains(" exited after 00:00."), "{lines:?}");
+    assert!(lines[0].ends_with(": sleep 0.4\n"), "{lines:?}");
+    assert!(lines[1].contains(" started 00:00."), "{lines:?}");
+    assert!(lines[1].ends_with(" ago: sleep 0.4\n"), "{lines:?}");
+    assert_eq!(" stdout   : done\n", lines[2]);
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ---------: ⏱ End\n"));
+    assert_eq!(Some(0), put.wait().await.code());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn output_held_open_by_background_process_ends_after_grace_period() {
+    let mut put = Linetime::run(to_os(vec![
+        "--grace-period",
+        "0.2",
+        "-s",
+        "sleep 10 & echo hello",
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout: hello\n"));
+    let end = assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ End\n"));
+    assert!(end < Duration::from_secs(2));
+    assert!(put.wait().await.success());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn additional_file_descriptor_is_read_as_stream() {
+    let mut put = Linetime::run(to_os(vec![
+        "--fd",
+        "3=progress",
+        "-s",
+        "echo 50% >&3; sleep 0.1; echo hello; sleep 0.1; echo 100% >&3",
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" progress: 50%\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout  : hello\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" progress: 100%\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" --------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn stderr_can_be_routed_to_stdout_and_stdout_to_file() {
+    let dir = TempDir::new("route");
+    let path = dir.join("out.log");
+    let mut put = Linetime::run(to_os(vec![
+        "--stderr-to",
+        "stdout",
+        "--route",
+        &format!("stdout={}", path.display()),
+        "-s",
+        "echo hello; sleep 0.1; echo world >&2",
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stderr: world\n"));
+    assert_command_output_end!(put);
+    assert!(put.wait().await.success());
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(log.ends_with(" stdout: hello\n"), "{log}");
+}
+
+#[tokio::test]
+async fn followed_file_is_read_until_command_has_exited() {
+    let dir = TempDir::new("follow");
+    let path = dir.join("app.log");
+    std::fs::write(&path, "old\n").unwrap();
+    let mut put = Linetime::run(to_os(vec![
+        "--follow",
+        &path.to_string_lossy(),
+        "-s",
+        &format!("sleep 0.2; echo hello >> '{}'; sleep 0.3", path.display()),
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" app.log: hello\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" -------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn followed_file_is_read_until_interrupted() {
+    let dir = TempDir::new("follow-interrupted");
+    let path = dir.join("app.log");
+    std::fs::write(&path, "").unwrap();
+    let mut put = Linetime::run(to_os(vec!["--follow", &path.to_string_lossy()]));
+
+    std::thread::sleep(Duration::from_millis(300));
+    std::fs::write(&path, "hello\n").unwrap();
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" app.log: hello\n"));
+    put.interrupt();
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" -------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn followed_files_with_same_name_are_rejected() {
+    let mut put = Linetime::run(to_os(vec![
+        "--follow",
+        "api/app.log",
+        "--follow",
+        "web/app.log",
+    ]));
+
+    assert_ok!(put.read_stderr(
+        "Invalid files to follow: The files 'api/app.log' and 'web/app.log' have the same stream \
+         name 'app.log'\n"
+    ));
+    assert_eq!(Some(1), put.wait().await.code());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn connections_to_socket_are_labeled_streams() {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+
+    let dir = TempDir::new("listen");
+    let path = dir.join("lt.sock");
+    let cast = dir.join("lt.cast");
+    let mut put = Linetime::run(to_os(vec![
+        "--listen",
+        &path.to_string_lossy(),
+        "--record",
+        &cast.to_string_lossy(),
+    ]));
+    let connect = || loop {
+        if let Ok(connection) = UnixStream::connect(&path) {
+            return connection;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let mut labeled = connect();
+    labeled
+        .write_all(b"#linetime label=tests\nhello\n")
+        .unwrap();
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" tests: hello\n"));
+    let mut unlabeled = connect();
+    unlabeled.write_all(b"world\n").unwrap();
+    assert_ok!(put.read_stdout_timestamp());
+    let name = format!("pid-{}", std::process::id());
+    assert_ok!(put.read_stdout(&format!(" {name}: world\n")));
+
+    // Open connections end when interrupted
+    put.interrupt();
+    assert_ok!(put.read_stdout_timestamp());
+    let separator = "-".repeat(name.len());
+    assert_ok!(put.read_stdout(&format!(" {separator}: ⏱ End\n")));
+    assert!(put.wait().await.success());
+    assert!(!path.exists());
+    let recording = std::fs::read_to_string(&cast).unwrap();
+    assert!(recording.contains(r"hello\n"), "{recording}");
+    assert!(recording.contains(r"world\n"), "{recording}");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn second_signal_ends_linetime_when_command_ignores_it() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut put = Linetime::run(to_os(vec![
+        "-s",
+        "trap '' INT; sleep 0.2; kill -INT $PPID; sleep 0.2; kill -INT $PPID; sleep 2",
+    ]));
+
+    assert_eq!(Some(libc::SIGINT), put.wait().await.signal());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn marks_are_written_on_signal_and_from_control_fifo() {
+    let dir = TempDir::new("control");
+    let path = dir.join("control");
+    let mut put = Linetime::run(to_os(vec![
+        "--control",
+        &path.to_string_lossy(),
+        "-s",
+        &format!(
+            "sleep 0.2; kill -USR1 $PPID; sleep 0.2; echo clicked > '{}'; sleep 0.2",
+            path.display()
+        ),
+    ]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ Mark 1\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ Mark 2: clicked\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
+
+#[tokio::test]
+async fn status_line_is_not_shown_when_stderr_is_not_a_terminal() {
+    let mut put = Linetime::run(to_os(vec!["--status", "-s", "echo hello"]));
+
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" stdout: hello\n"));
+    assert_ok!(put.read_stdout_timestamp());
+    assert_ok!(put.read_stdout(" ------: ⏱ End\n"));
+    assert!(put.wait().await.success());
+}
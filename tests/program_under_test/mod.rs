@@ -121,6 +121,15 @@ impl Linetime {
         }
     }
 
+    /// Reads the rest of a line, including linefeed, from the program's stdout
+    pub async fn read_stdout_line(&mut self) -> Result<String, std::io::Error> {
+        let mut line = vec![];
+        while line.last() != Some(&b'\n') {
+            line.push(self.stdout.read_u8().await?);
+        }
+        Ok(String::from_utf8(line).expect("Could not convert line on linetime stdout to UTF-8"))
+    }
+
     /// Reads some text from the program's stderr and checks that it matches the expected text,
     /// otherwise it returns an error
     pub async fn read_stderr(&mut self, expected_text: &str) -> Result<(), std::io::Error> {
@@ -134,6 +143,16 @@ impl Linetime {
         }
     }
 
+    /// Sends an interrupt signal to the program, like pressing Ctrl+C
+    #[cfg(unix)]
+    pub fn interrupt(&self) {
+        let pid = self.process.id().expect("Linetime should be running") as libc::pid_t;
+        // SAFETY: Sending a signal has no memory safety implications
+        unsafe {
+            libc::kill(pid, libc::SIGINT);
+        }
+    }
+
     /// Waits for program to end and checks that nothing more can be read from its stdout and stderr
     pub async fn wait(&mut self) -> std::process::ExitStatus {
         let mut stdout_rest = String::new();